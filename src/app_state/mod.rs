@@ -1,7 +1,34 @@
 use crate::map::MapState;
 use crate::viewport::ViewPortState;
+use std::mem;
 
 pub struct AppState {
     pub viewport_state: ViewPortState,
     pub map_state: MapState,
+    // the maps (and where they were looked at) the displayed region is part of, outermost first
+    pub parents: Vec<(MapState, ViewPortState)>,
+}
+
+impl AppState {
+    // shows the region of the selected hexes, the current map is kept to return to it
+    pub fn enter_region(&mut self) -> Result<(), String> {
+        let region = self.map_state.enter_region()?;
+        let parent = mem::replace(&mut self.map_state, region);
+        let viewport = mem::replace(&mut self.viewport_state, ViewPortState::new());
+        self.parents.push((parent, viewport));
+        Ok(())
+    }
+
+    pub fn leave_region(&mut self) {
+        let (parent, viewport) = match self.parents.pop() {
+            Some(p) => p,
+            None => {
+                eprintln!("The map is not a region of another map");
+                return;
+            }
+        };
+        let region = mem::replace(&mut self.map_state, parent);
+        self.viewport_state = viewport;
+        self.map_state.store_region(region);
+    }
 }
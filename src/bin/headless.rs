@@ -0,0 +1,156 @@
+use pf2e_terrain_gen::config;
+use pf2e_terrain_gen::map::{GeneratorKind, MapLayer, MapSettings, MapState};
+use pf2e_terrain_gen::rendering::text::render_text;
+use std::env;
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+// the same settings as the application (see `main.rs`), except for the smaller default size
+const MAP_SIZE: (u16, u16) = (120, 60);
+const SMOOTHING_ITERATIONS: u16 = 3;
+const SMOOTHING_STRENGTH: u8 = 40;
+const EROSION_ITERATIONS: u16 = 10;
+
+const USAGE: &str = "usage: headless [options]
+  --size WIDTHxHEIGHT    size of the map in hexes (default 120x60)
+  --seed SEED            seed of the map (random by default)
+  --generator NAME       procedural, random, wfc, sketch, image or heightmap
+  --area X,Y,WIDTH,HEIGHT  only prints these hexes
+  --underground          prints the underground instead of the surface
+  --plain                prints without colours
+  --stats                prints statistics of the map instead of the map
+  --json                 prints the statistics as JSON instead of the map
+  --names                prints the names of the map and where they are instead of the map";
+
+// Generates a map without opening a window and prints it as text (or its statistics)
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{e}");
+        process::exit(1);
+    }
+}
+
+fn run() -> Result<(), String> {
+    let options = Options::parse(env::args().skip(1))?;
+    // the built-in default config is used if the file cannot be loaded
+    if let Err(e) = config::reload() {
+        eprintln!("{e}");
+    }
+
+    let mut map_state = MapState::new(MapSettings {
+        dimensions: options.size,
+        iterations: SMOOTHING_ITERATIONS,
+        erosion_iterations: EROSION_ITERATIONS,
+        smoothing_strength: SMOOTHING_STRENGTH,
+        seed: options.seed,
+        generator: options.generator,
+    })?;
+    wait(&map_state);
+    if options.underground {
+        map_state.layer = MapLayer::Underground;
+    }
+
+    let map = map_state.visible_map().read().map_err(|e| e.to_string())?;
+    let (first, last) = match options.area {
+        Some((x, y, width, height)) => ((x, y), (x + width.max(1) - 1, y + height.max(1) - 1)),
+        None => ((0, 0), (usize::MAX, usize::MAX)),
+    };
+    match options.output {
+        Output::Text => print!("{}", render_text(&map, first, last, !options.plain)),
+        Output::Statistics => print!("{}", map.statistics()),
+        Output::Json => {
+            let json =
+                serde_json::to_string_pretty(&map.statistics()).map_err(|e| e.to_string())?;
+            println!("{json}");
+        }
+        // the underground is not named
+        Output::Names => {
+            for label in &map.labels {
+                let (x, y) = label.position;
+                println!("{:<30} {:?} at {x},{y}", label.text, label.kind);
+            }
+        }
+    }
+    Ok(())
+}
+
+// the generation runs in a thread of its own
+fn wait(map_state: &MapState) {
+    while map_state.is_generating() {
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+struct Options {
+    size: (u16, u16),
+    seed: Option<u64>,
+    generator: GeneratorKind,
+    area: Option<(usize, usize, usize, usize)>,
+    underground: bool,
+    plain: bool,
+    output: Output,
+}
+
+enum Output {
+    Text,
+    Statistics,
+    Json,
+    Names,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+        let mut options = Options {
+            size: MAP_SIZE,
+            seed: None,
+            generator: GeneratorKind::Procedural,
+            area: None,
+            underground: false,
+            plain: false,
+            output: Output::Text,
+        };
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("{arg} requires a value"));
+            match arg.as_str() {
+                "--size" => {
+                    let numbers = numbers(&value()?, 'x')?;
+                    let (width, height) = match numbers[..] {
+                        [width, height] => (width, height),
+                        _ => return Err(String::from("--size requires WIDTHxHEIGHT")),
+                    };
+                    let size = |n: usize| u16::try_from(n).map_err(|_| "the map is too large");
+                    options.size = (size(width)?, size(height)?);
+                }
+                "--seed" => {
+                    let seed = value()?;
+                    options.seed =
+                        Some(seed.parse().map_err(|_| format!("invalid seed '{seed}'"))?);
+                }
+                "--generator" => options.generator = value()?.parse()?,
+                "--area" => match numbers(&value()?, ',')?[..] {
+                    [x, y, width, height] => options.area = Some((x, y, width, height)),
+                    _ => return Err(String::from("--area requires X,Y,WIDTH,HEIGHT")),
+                },
+                "--underground" => options.underground = true,
+                "--plain" => options.plain = true,
+                "--stats" => options.output = Output::Statistics,
+                "--json" => options.output = Output::Json,
+                "--names" => options.output = Output::Names,
+                "--help" => return Err(String::from(USAGE)),
+                _ => return Err(format!("unknown option '{arg}'\n{USAGE}")),
+            }
+        }
+        Ok(options)
+    }
+}
+
+fn numbers(text: &str, separator: char) -> Result<Vec<usize>, String> {
+    text.split(separator)
+        .map(|n| {
+            n.trim()
+                .parse()
+                .map_err(|_| format!("invalid number '{n}'"))
+        })
+        .collect()
+}
@@ -0,0 +1,952 @@
+use crate::map::environments::{self, Environment, EnvironmentDefinition, EnvironmentRegistry};
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::SystemTime;
+use toml::value::{Table, Value};
+
+pub const CONFIG_PATH: &str = "./config/generator.toml";
+// used if there is no config file, so the application also works when started from anywhere else
+const DEFAULT_CONFIG: &str = include_str!("../../config/generator.toml");
+// tables listing environments (or characters) are never merged with the default config, since that
+// would bring back entries that were left out on purpose
+const WHOLE_TABLES: [&str; 9] = [
+    "environments",
+    "transform_chances",
+    "weights",
+    "neighbours",
+    "legend",
+    "palette",
+    "environment_weights",
+    "environment_heights",
+    "terrain",
+];
+
+static CONFIG: Lazy<RwLock<Arc<GeneratorConfig>>> = Lazy::new(|| {
+    RwLock::new(Arc::new(
+        GeneratorConfig::load(DEFAULT_CONFIG).expect("the default config must be valid"),
+    ))
+});
+// modification time of the config file when it was last loaded
+static LOADED_AT: Mutex<Option<SystemTime>> = Mutex::new(None);
+
+// Everything designers might want to tune without touching the code. Latitude bands are given as
+// fractions of the map's height (e.g. `0.125` for a band of 1/8th of the map)
+#[derive(Debug)]
+pub struct GeneratorConfig {
+    pub environments: BTreeMap<Environment, EnvironmentConfig>,
+    pub procedural: ProceduralConfig,
+    pub wfc: WfcConfig,
+    pub sketch: SketchConfig,
+    pub import: ImportConfig,
+    pub heightmap: HeightmapConfig,
+    pub settlements: SettlementConfig,
+    pub roads: RoadConfig,
+    pub underground: UndergroundConfig,
+    pub elevation: ElevationConfig,
+    pub undersea: UnderseaConfig,
+    pub climate: ClimateConfig,
+    pub erosion: ErosionConfig,
+    pub region: RegionConfig,
+    pub worldographer: WorldographerConfig,
+    pub geojson: GeoJsonConfig,
+    pub atlas: AtlasConfig,
+    pub names: NamesConfig,
+}
+
+#[derive(Debug)]
+pub struct EnvironmentConfig {
+    // chance to generate a tile of this type (relative to the sum of all base chances), only
+    // used by the random generator
+    pub base_chance: u16,
+    // chance in percent to transform into this environment based on the tile's current
+    // environment when smoothing the map, only used by the random generator
+    pub transform_chances: HashMap<Environment, u16>,
+}
+
+// layout of the config file, environments are referred to by their keys which can only be
+// resolved once the environments defined in the file are known
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    environments: BTreeMap<String, EnvironmentEntry>,
+    procedural: ProceduralConfig,
+    wfc: WfcEntry,
+    sketch: SketchEntry,
+    import: ImportEntry,
+    heightmap: HeightmapConfig,
+    settlements: SettlementEntry,
+    roads: RoadConfig,
+    underground: UndergroundConfig,
+    elevation: ElevationEntry,
+    undersea: UnderseaConfig,
+    climate: ClimateConfig,
+    erosion: ErosionConfig,
+    region: RegionConfig,
+    worldographer: WorldographerEntry,
+    geojson: GeoJsonConfig,
+    atlas: AtlasConfig,
+    names: NamesConfig,
+}
+
+// properties that are not set keep their built-in/previously registered value
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EnvironmentEntry {
+    name: Option<String>,
+    color: Option<[u8; 3]>,
+    traits: Option<Vec<String>>,
+    // `0` if the environment cannot be traversed on foot
+    movement_cost: Option<u8>,
+    icon: Option<String>,
+    glyph: Option<char>,
+    #[serde(default)]
+    base_chance: u16,
+    #[serde(default)]
+    transform_chances: HashMap<String, u16>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ProceduralConfig {
+    pub water: WaterConfig,
+    pub ice: IceConfig,
+    pub land: LandConfig,
+    pub tectonics: TectonicsConfig,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct WaterConfig {
+    // odds (in tenths) for a hex to become water depending on its already generated neighbours
+    pub odds_next_to_land: u32,
+    pub odds_next_to_water: u32,
+    // additional odds (in tenths) inside/outside the polar band
+    pub polar_bonus: u32,
+    pub bonus: u32,
+    pub polar_band: f32,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct IceConfig {
+    // water can only freeze within this distance of the poles
+    pub band: f32,
+    // chances in percent that are reached at the poles and linearly decrease towards the band's edge
+    pub max_freeze_chance: u32,
+    // frozen water that doesn't become arctic becomes a glacier
+    pub max_arctic_chance: u32,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct LandConfig {
+    // deserts can only be generated within this distance of the equator
+    pub desert_band: f32,
+    // chance in percent at the equator, linearly decreasing towards the band's edge
+    pub max_desert_chance: u32,
+    // chance in percent for aerial terrain, reduced for every aerial neighbour
+    pub aerial_chance: u32,
+    pub aerial_chance_per_neighbour: u32,
+    // chance in percent for volcanic terrain, increased for every mountain/desert/volcanic neighbour
+    pub volcanic_chance: u32,
+    pub volcanic_chance_per_neighbour: u32,
+    // chance in percent at the poles, linearly decreasing towards the equator
+    pub max_plains_chance: u32,
+    // the following chances are only rolled if none of the environments above were chosen, in
+    // order, swamps are generated if neither succeeds
+    pub forest_chance: u32,
+    pub mountain_chance: u32,
+}
+
+#[derive(Debug)]
+pub struct WfcConfig {
+    // how likely each environment is to be chosen for a hex (relative to each other), environments
+    // without a weight are never generated
+    pub weights: HashMap<Environment, u32>,
+    // environments with an entry may only be placed next to the listed environments (and
+    // themselves), two environments can only be neighbours if both of them allow it
+    pub neighbours: HashMap<Environment, Vec<Environment>>,
+    // percentage by which the weight increases for every neighbour of the same environment
+    pub clustering: u32,
+    // contradictions that may be resolved by undoing previous choices before a chunk is restarted
+    pub max_backtracks: u32,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WfcEntry {
+    weights: HashMap<String, u32>,
+    neighbours: HashMap<String, Vec<String>>,
+    clustering: u32,
+    max_backtracks: u32,
+}
+
+#[derive(Debug)]
+pub struct SketchConfig {
+    // text file with the rough shape of the map, see `legend`
+    pub path: String,
+    // the environment of every character of the sketch
+    pub legend: HashMap<char, Environment>,
+    // maximum distance in percent of a hex of the coarser grid by which the borders between its
+    // environments are shifted whenever the grid is subdivided
+    pub jitter: u16,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SketchEntry {
+    path: String,
+    legend: HashMap<String, String>,
+    jitter: u16,
+}
+
+#[derive(Debug)]
+pub struct ImportConfig {
+    // image painted with the colours of the environments
+    pub path: String,
+    // colours of the environments in the image, the colours of all surface environments are used if
+    // it is empty
+    pub palette: HashMap<Environment, [u8; 3]>,
+    // colours further away from every colour of the palette are reported
+    pub tolerance: u16,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ImportEntry {
+    path: String,
+    palette: HashMap<String, [u8; 3]>,
+    tolerance: u16,
+}
+
+#[derive(Debug)]
+pub struct SettlementConfig {
+    // amount of land hexes per settlement of the respective size
+    pub land_per_city: u32,
+    pub land_per_town: u32,
+    pub land_per_village: u32,
+    // minimum distance in hexes between a new settlement of the respective size and any other one
+    pub city_spacing: u16,
+    pub town_spacing: u16,
+    pub village_spacing: u16,
+    // how attractive each environment is to settle in, environments without a weight are never settled
+    pub environment_weights: HashMap<Environment, u32>,
+    // added to the weight of hexes next to water/plains/on a river
+    pub coast_bonus: u32,
+    pub plains_bonus: u32,
+    pub river_bonus: u32,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SettlementEntry {
+    land_per_city: u32,
+    land_per_town: u32,
+    land_per_village: u32,
+    city_spacing: u16,
+    town_spacing: u16,
+    village_spacing: u16,
+    environment_weights: HashMap<String, u32>,
+    coast_bonus: u32,
+    plains_bonus: u32,
+    river_bonus: u32,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RoadConfig {
+    // settlements are only connected to settlements within this distance (in hexes)
+    pub max_route_length: u16,
+    // routes may cost at most this many times as much as a straight route across plains
+    pub max_detour: u16,
+    // costs (in percent of a hex of plains) of following an existing road and of crossing a water
+    // hex by bridge/ferry, cheap roads make routes merge into a network
+    pub road_cost: u32,
+    pub crossing_cost: u32,
+    // water hexes further away from land than this cannot be crossed
+    pub max_crossing_distance: u16,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct UndergroundConfig {
+    // chances in percent for a hex to start out as a cavern, depending on the surface above it
+    pub cavern_chance_under_mountains: u32,
+    pub cavern_chance: u32,
+    // chance in percent for a hex below a lake (see `UnderseaConfig::max_lake_size`) to start out
+    // as a flooded cave
+    pub flooded_chance_under_lakes: u32,
+    // the caverns take shape while smoothing
+    pub smoothing_iterations: u16,
+    // chances in per mille for a cavern to be connected to the surface, depending on the surface
+    pub entrance_chance_under_mountains: u32,
+    pub entrance_chance: u32,
+}
+
+// Elevations are given in metres relative to the sea level
+#[derive(Debug)]
+pub struct ElevationConfig {
+    // the sea floor gradually drops to this depth within this many hexes of the coast
+    pub shelf_depth: i16,
+    pub shelf_width: u16,
+    // further out it drops by this much per hex until it reaches the abyssal plain
+    pub slope_per_hex: i16,
+    pub abyssal_depth: i16,
+    // chance in per mille for a hex of the abyssal plain to start a trench, trenches run for this
+    // many hexes and are up to this deep
+    pub trench_chance: u32,
+    pub trench_length: u16,
+    pub trench_depth: i16,
+    // height gained per hex of distance to the coast
+    pub land_rise_per_hex: i16,
+    // additional height of hexes of the given environments
+    pub environment_heights: HashMap<Environment, i16>,
+    // random variation in percent
+    pub variation: u16,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ElevationEntry {
+    shelf_depth: i16,
+    shelf_width: u16,
+    slope_per_hex: i16,
+    abyssal_depth: i16,
+    trench_chance: u32,
+    trench_length: u16,
+    trench_depth: i16,
+    land_rise_per_hex: i16,
+    environment_heights: HashMap<String, i16>,
+    variation: u16,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct UnderseaConfig {
+    // bodies of water with at most this many hexes are lakes and stay aquatic, anything larger is
+    // divided into the following zones by its depth
+    pub max_lake_size: u32,
+    // maximum depth in metres of each zone, anything deeper is a trench
+    pub shallows_depth: i16,
+    pub open_sea_depth: i16,
+    pub deep_ocean_depth: i16,
+    // chances in percent for shallows to become reefs within this distance of the equator/kelp
+    // forests outside of it
+    pub reef_band: f32,
+    pub reef_chance: u32,
+    pub kelp_chance: u32,
+}
+
+// Temperatures are given in degrees Celsius, precipitation (and the moisture the wind carries) in
+// millimetres per year
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ClimateConfig {
+    // without a climate, deserts, forests, plains and swamps are left as the generator placed them
+    pub enabled: bool,
+    // temperatures at the sea level, the temperature drops by this much per 1000 metres of height
+    pub equator_temperature: i8,
+    pub pole_temperature: i8,
+    pub lapse_rate: f32,
+    // moisture the wind picks up per hex of warm sea, up to the maximum warm air can carry
+    pub evaporation: u16,
+    pub max_moisture: u16,
+    // percentage of the carried moisture that rains down per hex, plus this much per 1000 metres the
+    // wind has to climb
+    pub rainfall: u32,
+    pub orographic_rainfall: u32,
+    // percentage of the rain on land that evaporates again and is carried further
+    pub recycling: u32,
+    // percentage by which the sinking air of the subtropics and the poles reduces the rainfall
+    pub subsidence_drying: u32,
+    // random variation of the precipitation in percent
+    pub variation: u16,
+    // deserts, forests, plains and swamps are reclassified by these thresholds: colder land turns
+    // arctic, drier land turns into deserts, wetter land into forests and, if low enough, swamps
+    pub arctic_temperature: i8,
+    pub desert_precipitation: u16,
+    pub forest_precipitation: u16,
+    pub swamp_precipitation: u16,
+    pub swamp_max_elevation: i16,
+}
+
+// Heights are given in metres, the amount of water in metres of rain per year (on a single hex)
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ErosionConfig {
+    // sediment the water can carry per metre of slope, grows with the square root of the water flowing
+    pub sediment_capacity: f32,
+    // percentages of the remaining capacity that is eroded/of the sediment beyond the capacity that is
+    // deposited per hex
+    pub erosion_rate: u32,
+    pub deposition_rate: u32,
+    // maximum height a hex loses per iteration
+    pub max_erosion: u16,
+    // percentage of the sediment rivers carry into the sea that settles at their mouth (forming deltas)
+    pub delta_rate: u32,
+    // steeper slopes (difference in height between neighbours) slump, moving this percentage of the
+    // excess height downhill per iteration
+    pub talus: u16,
+    pub thermal_rate: u32,
+    // hexes with at least this much water flowing through them carry rivers
+    pub river_flow: u32,
+}
+
+// Heightmaps are greyscale images with one pixel per hex, elevations are given in metres
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct HeightmapConfig {
+    // image imported by the heightmap generator
+    pub path: String,
+    // the elevations of black and white pixels, in between they are linear
+    pub min_elevation: i16,
+    pub max_elevation: i16,
+    // imported land above this elevation becomes mountains
+    pub mountain_elevation: i16,
+}
+
+impl HeightmapConfig {
+    // the grey value of the elevation, elevations beyond the range are clamped
+    pub fn to_grey(&self, elevation: i16) -> u16 {
+        let (min, max) = (self.min_elevation as f32, self.max_elevation as f32);
+        ((elevation as f32 - min) / (max - min) * u16::MAX as f32)
+            .round()
+            .clamp(0f32, u16::MAX as f32) as u16
+    }
+
+    // the elevation of the (possibly interpolated) grey value
+    pub fn from_grey(&self, grey: f32) -> i16 {
+        let (min, max) = (self.min_elevation as f32, self.max_elevation as f32);
+        (min + grey / u16::MAX as f32 * (max - min)).round() as i16
+    }
+}
+
+// Exported maps are opened in Worldographer (or Hexographer), which uses its own terrain types
+#[derive(Debug)]
+pub struct WorldographerConfig {
+    // terrain type of every environment, environments that are not listed are exported as blank
+    pub terrain: HashMap<Environment, String>,
+    pub features: SettlementFeatures,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WorldographerEntry {
+    terrain: HashMap<String, String>,
+    features: SettlementFeatures,
+}
+
+// feature (icon) types of the settlements
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SettlementFeatures {
+    pub village: String,
+    pub town: String,
+    pub city: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct GeoJsonConfig {
+    // merges the contiguous hexes of the same environment into a single polygon, otherwise every hex
+    // is a polygon of its own
+    pub dissolve: bool,
+}
+
+// Atlases are printed across several sheets of paper, sizes are given in millimetres
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct AtlasConfig {
+    pub paper: Paper,
+    // width of a hex on paper
+    pub hex_size: f32,
+    // blank border around every sheet
+    pub margin: f32,
+    // width of the strip of the map that is printed on both of two neighbouring sheets
+    pub overlap: f32,
+}
+
+#[derive(Deserialize, Copy, Clone, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Paper {
+    A4,
+    Letter,
+}
+
+// Names are made up from the vocabularies in a file of their own, see `names::load_vocabulary`
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct NamesConfig {
+    // the cultures with their sample names and the patterns of the names of every kind of area
+    pub path: String,
+    // amount of preceding letters the next letter of a name depends on, higher orders stay closer to
+    // the sample names
+    pub order: usize,
+    // smaller areas are not named, rivers are measured along their longest branch
+    pub min_landmass_size: usize,
+    pub min_sea_size: usize,
+    pub min_lake_size: usize,
+    pub min_environment_size: usize,
+    pub min_river_length: usize,
+}
+
+// Regions are maps of a few hexes of another map at a finer scale
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RegionConfig {
+    // hexes of the region across a single hex of the map it is part of
+    pub scale: u16,
+    // maximum distance in percent of a hex of the parent map by which the borders between its
+    // environments are shifted (randomly for every hex of the region)
+    pub jitter: u16,
+    // chance in percent for a hex to use the `transform_chances` of its environment
+    pub detail: u16,
+    // file the regions that have been generated are saved to (see `region_gen::RegionRecord`)
+    pub path: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct TectonicsConfig {
+    // without plates, volcanic hexes are scattered based on their neighbours only
+    pub enabled: bool,
+    pub plates: u16,
+    // hexes within this distance of a plate boundary are affected by it, the closer the stronger
+    pub boundary_width: u16,
+    // height in metres that hexes at the boundary are raised by where plates collide/lowered by
+    // where they drift apart (at full speed)
+    pub max_uplift: u16,
+    pub max_rift_depth: u16,
+    // odds (in tenths) by which the chance for water decreases where plates collide/increases where
+    // they drift apart (at full speed)
+    pub land_odds: u32,
+    pub rift_water_odds: u32,
+    // chances in percent for mountains where plates collide (at full speed) and for volcanoes at
+    // this distance from the boundary (volcanic arcs)
+    pub mountain_chance: u32,
+    pub arc_distance: u16,
+    pub arc_volcanic_chance: u32,
+}
+
+impl GeneratorConfig {
+    // the config with the registry of the environments defined in it, which is only installed once
+    // the whole config is valid (see `load`)
+    pub(crate) fn parse(content: &str) -> Result<(GeneratorConfig, EnvironmentRegistry), String> {
+        let mut table: Table = toml::from_str(content).map_err(|e| e.to_string())?;
+        let defaults: Table = toml::from_str(DEFAULT_CONFIG).map_err(|e| e.to_string())?;
+        fill_missing(&mut table, &defaults);
+        let file: ConfigFile = Value::Table(table)
+            .try_into()
+            .map_err(|e: toml::de::Error| e.to_string())?;
+
+        // environments that are not defined in the file (anymore) are not available either, unless
+        // they are built in or registered
+        let built_in = EnvironmentRegistry::with_definitions(Vec::new())?;
+        for (key, entry) in &file.environments {
+            if built_in.find(key).is_none() && entry.color.is_none() {
+                return Err(format!("the new environment '{key}' requires a color"));
+            }
+        }
+        let registry = EnvironmentRegistry::with_definitions(
+            file.environments
+                .iter()
+                .map(|(key, entry)| entry.definition(key, &built_in))
+                .collect(),
+        )?;
+        // the environments have to be told apart in text (see `rendering::text`)
+        let mut glyphs: HashMap<char, &str> = HashMap::new();
+        for definition in registry.definitions() {
+            let glyph = definition.glyph();
+            if glyph.is_whitespace() || glyph.is_control() {
+                return Err(format!(
+                    "the glyph of the environment '{}' must be visible",
+                    definition.key
+                ));
+            }
+            if let Some(other) = glyphs.insert(glyph, &definition.key) {
+                return Err(format!(
+                    "the environments '{other}' and '{}' have the same glyph '{glyph}'",
+                    definition.key
+                ));
+            }
+        }
+        for (key, entry) in &file.environments {
+            for target in entry.transform_chances.keys() {
+                if registry.find(target).is_none() {
+                    return Err(format!("unknown environment '{target}' in '{key}'"));
+                }
+            }
+        }
+        let referenced: [(&str, Vec<&String>); 6] = [
+            ("sketch", file.sketch.legend.values().collect()),
+            ("import", file.import.palette.keys().collect()),
+            ("worldographer", file.worldographer.terrain.keys().collect()),
+            (
+                "wfc",
+                file.wfc
+                    .weights
+                    .keys()
+                    .chain(file.wfc.neighbours.keys())
+                    .chain(file.wfc.neighbours.values().flatten())
+                    .collect(),
+            ),
+            (
+                "settlements",
+                file.settlements.environment_weights.keys().collect(),
+            ),
+            (
+                "elevation",
+                file.elevation.environment_heights.keys().collect(),
+            ),
+        ];
+        for (section, keys) in referenced {
+            for key in keys {
+                if registry.find(key).is_none() {
+                    return Err(format!("unknown environment '{key}' in '{section}'"));
+                }
+            }
+        }
+        // the possible environments of a hex are stored as bits of an `u64`
+        if file.wfc.weights.values().filter(|&&w| w > 0).count() > 64 {
+            return Err(String::from(
+                "the wfc generator supports at most 64 weighted environments",
+            ));
+        }
+        if let Some(key) = file.sketch.legend.keys().find(|k| k.chars().count() != 1) {
+            return Err(format!(
+                "the sketch legend may only contain single characters, found '{key}'"
+            ));
+        }
+        if file.heightmap.min_elevation >= file.heightmap.max_elevation {
+            return Err(String::from(
+                "the minimum elevation of heightmaps must be below the maximum elevation",
+            ));
+        }
+        if file.atlas.hex_size <= 0f32 || file.atlas.margin < 0f32 || file.atlas.overlap < 0f32 {
+            return Err(String::from(
+                "the hex size of atlases must be positive, their margin and overlap not negative",
+            ));
+        }
+        if file.names.order == 0 {
+            return Err(String::from(
+                "the order of the name generator must be at least 1",
+            ));
+        }
+        if file.region.scale == 0 {
+            return Err(String::from("the region scale must be at least 1"));
+        }
+        if file.settlements.land_per_city == 0
+            || file.settlements.land_per_town == 0
+            || file.settlements.land_per_village == 0
+        {
+            return Err(String::from(
+                "the land per settlement must be greater than 0",
+            ));
+        }
+
+        let mut environments = BTreeMap::new();
+        for (key, entry) in file.environments {
+            let environment = resolve(&registry, &key)?;
+            let mut transform_chances = HashMap::new();
+            for (target, chance) in entry.transform_chances {
+                transform_chances.insert(resolve(&registry, &target)?, chance);
+            }
+            environments.insert(
+                environment,
+                EnvironmentConfig {
+                    base_chance: entry.base_chance,
+                    transform_chances,
+                },
+            );
+        }
+
+        let wfc = file.wfc;
+        let mut neighbours = HashMap::new();
+        for (key, allowed) in wfc.neighbours {
+            let allowed = allowed
+                .iter()
+                .map(|k| resolve(&registry, k))
+                .collect::<Result<_, _>>()?;
+            neighbours.insert(resolve(&registry, &key)?, allowed);
+        }
+        let sketch = file.sketch;
+        let mut legend = HashMap::new();
+        for (key, environment) in sketch.legend {
+            if let Some(character) = key.chars().next() {
+                legend.insert(character, resolve(&registry, &environment)?);
+            }
+        }
+        let settlements = file.settlements;
+        let elevation = file.elevation;
+
+        let config = GeneratorConfig {
+            environments,
+            procedural: file.procedural,
+            wfc: WfcConfig {
+                weights: resolve_all(&registry, wfc.weights)?,
+                neighbours,
+                clustering: wfc.clustering,
+                max_backtracks: wfc.max_backtracks,
+            },
+            sketch: SketchConfig {
+                path: sketch.path,
+                legend,
+                jitter: sketch.jitter,
+            },
+            import: ImportConfig {
+                path: file.import.path,
+                palette: resolve_all(&registry, file.import.palette)?,
+                tolerance: file.import.tolerance,
+            },
+            heightmap: file.heightmap,
+            worldographer: WorldographerConfig {
+                terrain: resolve_all(&registry, file.worldographer.terrain)?,
+                features: file.worldographer.features,
+            },
+            geojson: file.geojson,
+            atlas: file.atlas,
+            names: file.names,
+            settlements: SettlementConfig {
+                land_per_city: settlements.land_per_city,
+                land_per_town: settlements.land_per_town,
+                land_per_village: settlements.land_per_village,
+                city_spacing: settlements.city_spacing,
+                town_spacing: settlements.town_spacing,
+                village_spacing: settlements.village_spacing,
+                environment_weights: resolve_all(&registry, settlements.environment_weights)?,
+                coast_bonus: settlements.coast_bonus,
+                plains_bonus: settlements.plains_bonus,
+                river_bonus: settlements.river_bonus,
+            },
+            roads: file.roads,
+            underground: file.underground,
+            elevation: ElevationConfig {
+                shelf_depth: elevation.shelf_depth,
+                shelf_width: elevation.shelf_width,
+                slope_per_hex: elevation.slope_per_hex,
+                abyssal_depth: elevation.abyssal_depth,
+                trench_chance: elevation.trench_chance,
+                trench_length: elevation.trench_length,
+                trench_depth: elevation.trench_depth,
+                land_rise_per_hex: elevation.land_rise_per_hex,
+                environment_heights: resolve_all(&registry, elevation.environment_heights)?,
+                variation: elevation.variation,
+            },
+            undersea: file.undersea,
+            climate: file.climate,
+            erosion: file.erosion,
+            region: file.region,
+        };
+        Ok((config, registry))
+    }
+
+    // the environments of the config are available once it has been loaded
+    fn load(content: &str) -> Result<GeneratorConfig, String> {
+        let (config, registry) = GeneratorConfig::parse(content)?;
+        environments::install(registry)?;
+        Ok(config)
+    }
+}
+
+impl EnvironmentEntry {
+    // properties that are not set keep their built-in/registered value
+    fn definition(&self, key: &str, built_in: &EnvironmentRegistry) -> EnvironmentDefinition {
+        let previous = built_in.find(key).map(|e| built_in.definition(e).clone());
+        let previous = previous.as_ref();
+        EnvironmentDefinition {
+            id: Environment::NONE,
+            key: key.to_string(),
+            name: self
+                .name
+                .clone()
+                .or_else(|| previous.map(|d| d.name.clone()))
+                .unwrap_or_else(|| key.to_string()),
+            color: self
+                .color
+                .or_else(|| previous.map(|d| d.color))
+                .unwrap_or_default(),
+            traits: self
+                .traits
+                .clone()
+                .or_else(|| previous.map(|d| d.traits.clone()))
+                .unwrap_or_default(),
+            movement_cost: match self.movement_cost {
+                Some(0) => None,
+                Some(cost) => Some(cost),
+                None => previous.and_then(|d| d.movement_cost),
+            },
+            icon: self
+                .icon
+                .clone()
+                .or_else(|| previous.and_then(|d| d.icon.clone())),
+            glyph: self.glyph.or_else(|| previous.and_then(|d| d.glyph)),
+        }
+    }
+}
+
+// Sections and settings missing from the config file are taken from the default config, so that
+// config files of previous versions remain valid
+fn fill_missing(table: &mut Table, defaults: &Table) {
+    for (key, default) in defaults {
+        match (table.get_mut(key), default) {
+            (None, _) => {
+                table.insert(key.clone(), default.clone());
+            }
+            (Some(Value::Table(table)), Value::Table(default))
+                if !WHOLE_TABLES.contains(&key.as_str()) =>
+            {
+                fill_missing(table, default)
+            }
+            _ => {}
+        }
+    }
+}
+
+fn resolve(registry: &EnvironmentRegistry, key: &str) -> Result<Environment, String> {
+    registry
+        .find(key)
+        .ok_or_else(|| format!("unknown environment '{key}'"))
+}
+
+fn resolve_all<T>(
+    registry: &EnvironmentRegistry,
+    values: HashMap<String, T>,
+) -> Result<HashMap<Environment, T>, String> {
+    values
+        .into_iter()
+        .map(|(key, value)| Ok((resolve(registry, &key)?, value)))
+        .collect()
+}
+
+// converts a latitude band (fraction of the map's height) into an amount of rows
+pub fn band_rows(band: f32, max_y: usize) -> usize {
+    (band * max_y as f32) as usize
+}
+
+// the config as it was when calling this function, so that it cannot change during a generation
+pub fn current() -> Arc<GeneratorConfig> {
+    match CONFIG.read() {
+        Ok(config) => config.clone(),
+        Err(e) => e.into_inner().clone(),
+    }
+}
+
+// (re)loads the config file, the previous config stays active if the file is missing or invalid
+pub fn reload() -> Result<(), String> {
+    let modified = fs::metadata(CONFIG_PATH)
+        .and_then(|m| m.modified())
+        .map_err(|e| format!("Failed to read config '{CONFIG_PATH}': {e}"))?;
+    *LOADED_AT.lock().map_err(|e| e.to_string())? = Some(modified);
+
+    let content = fs::read_to_string(CONFIG_PATH)
+        .map_err(|e| format!("Failed to read config '{CONFIG_PATH}': {e}"))?;
+    let config = GeneratorConfig::load(&content)
+        .map_err(|e| format!("Invalid config '{CONFIG_PATH}': {e}"))?;
+
+    *CONFIG.write().map_err(|e| e.to_string())? = Arc::new(config);
+    eprintln!("Loaded config '{CONFIG_PATH}'");
+    Ok(())
+}
+
+// cheap enough to be called every frame, only reloads if the file was modified since it was loaded
+pub fn reload_if_changed() {
+    let modified = match fs::metadata(CONFIG_PATH).and_then(|m| m.modified()) {
+        Ok(m) => m,
+        Err(_) => return,
+    };
+    let changed = match LOADED_AT.lock() {
+        Ok(loaded_at) => *loaded_at != Some(modified),
+        Err(_) => false,
+    };
+    if changed {
+        if let Err(e) = reload() {
+            eprintln!("{e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_sections_are_taken_from_the_default_config() {
+        let (config, _) = GeneratorConfig::parse("[names]\norder = 3\n").unwrap();
+        let default = GeneratorConfig::parse(DEFAULT_CONFIG).unwrap().0;
+        assert_eq!(config.names.order, 3);
+        assert_eq!(config.names.path, default.names.path);
+        assert_eq!(config.region.scale, default.region.scale);
+        assert_eq!(
+            config.procedural.tectonics.plates,
+            default.procedural.tectonics.plates
+        );
+    }
+
+    #[test]
+    fn tables_of_environments_are_not_merged() {
+        let config =
+            GeneratorConfig::parse("[settlements]\nenvironment_weights = { plains = 1 }\n");
+        assert_eq!(config.unwrap().0.settlements.environment_weights.len(), 1);
+    }
+
+    #[test]
+    fn invalid_configs_leave_the_environments_untouched() {
+        let invalid = "[environments.badlands]\ncolor = [1, 2, 3]\n[region]\nscale = 0\n";
+        assert!(GeneratorConfig::load(invalid).is_err());
+        assert!(Environment::from_key("badlands").is_none());
+    }
+
+    #[test]
+    fn environments_left_out_are_removed() {
+        // the default config is loaded once the config is first used
+        current();
+        GeneratorConfig::load("[environments.tundra]\ncolor = [1, 2, 3]\n").unwrap();
+        assert!(Environment::from_key("tundra").is_some());
+        GeneratorConfig::load(DEFAULT_CONFIG).unwrap();
+        assert!(Environment::from_key("tundra").is_none());
+    }
+
+    #[test]
+    fn registered_environments_survive_reloads() {
+        current();
+        let definition = EnvironmentDefinition {
+            id: Environment::NONE,
+            key: String::from("registered"),
+            name: String::from("Registered"),
+            color: [1, 2, 3],
+            traits: Vec::new(),
+            movement_cost: Some(1),
+            icon: None,
+            glyph: Some('Ω'),
+        };
+        let registered = environments::register(definition).unwrap();
+        GeneratorConfig::load(DEFAULT_CONFIG).unwrap();
+        assert_eq!(Environment::from_key("registered"), Some(registered));
+        assert_eq!(registered.glyph(), 'Ω');
+        // the config may still redefine them
+        let redefined = "[environments.registered]\nmovement_cost = 2\n";
+        let (_, registry) = GeneratorConfig::parse(redefined).unwrap();
+        let definition = registry.definition(registered);
+        assert_eq!(definition.movement_cost, Some(2));
+        assert_eq!(definition.glyph(), 'Ω');
+    }
+
+    #[test]
+    fn glyphs_must_be_unique() {
+        let duplicate = "[environments.tundra]\ncolor = [1, 2, 3]\nglyph = \"~\"\n";
+        assert!(GeneratorConfig::parse(duplicate).is_err());
+        // without a glyph of its own, the first letter of the key is used
+        let fallback = "[environments.tundra]\ncolor = [1, 2, 3]\n";
+        let (_, registry) = GeneratorConfig::parse(fallback).unwrap();
+        let tundra = registry.find("tundra").unwrap();
+        assert_eq!(registry.definition(tundra).glyph(), 't');
+    }
+
+    #[test]
+    fn unknown_settings_are_rejected() {
+        assert!(GeneratorConfig::parse("[names]\nordr = 3\n").is_err());
+    }
+}
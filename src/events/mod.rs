@@ -1,7 +1,14 @@
 use crate::app_state::AppState;
-use crate::image::save_as_png;
+use crate::config;
+use crate::export::atlas::save_atlas;
+use crate::export::geojson::save_geojson;
+use crate::export::tmx::save_tmx;
+use crate::export::worldographer::save_worldographer;
+use crate::image::{save_as_png, save_heightmap};
+use crate::rendering::HexRenderer;
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
+use sdl2::mouse::MouseButton;
 use sdl2::EventPump;
 
 pub fn handle_events(event_pump: &mut EventPump, app_state: &mut AppState) -> Result<bool, String> {
@@ -18,7 +25,10 @@ pub fn handle_events(event_pump: &mut EventPump, app_state: &mut AppState) -> Re
                 keycode: Some(Keycode::R),
                 ..
             } => {
-                app_state.map_state.generate_map()?;
+                app_state.map_state.reseed();
+                if let Err(e) = app_state.map_state.generate_map() {
+                    eprintln!("{e}");
+                }
             }
             Event::KeyDown {
                 keycode: Some(Keycode::P),
@@ -28,6 +38,144 @@ pub fn handle_events(event_pump: &mut EventPump, app_state: &mut AppState) -> Re
                 // 40: 300x300 works,
                 save_as_png(&app_state.map_state, 40)?;
             }
+            Event::KeyDown {
+                keycode: Some(Keycode::H),
+                ..
+            } => {
+                if let Err(e) = save_heightmap(&app_state.map_state, &config::current().heightmap) {
+                    eprintln!("Failed to save the heightmap: {e}");
+                }
+            }
+            Event::KeyDown {
+                keycode: Some(Keycode::W),
+                ..
+            } => {
+                let config = config::current();
+                if let Err(e) = save_worldographer(&app_state.map_state, &config.worldographer) {
+                    eprintln!("Failed to save the Worldographer map: {e}");
+                }
+            }
+            Event::KeyDown {
+                keycode: Some(Keycode::T),
+                ..
+            } => {
+                if let Err(e) = save_tmx(&app_state.map_state) {
+                    eprintln!("Failed to save the Tiled map: {e}");
+                }
+            }
+            Event::KeyDown {
+                keycode: Some(Keycode::J),
+                ..
+            } => {
+                if let Err(e) = save_geojson(&app_state.map_state, &config::current().geojson) {
+                    eprintln!("Failed to save the GeoJSON: {e}");
+                }
+            }
+            Event::KeyDown {
+                keycode: Some(Keycode::A),
+                ..
+            } => {
+                if let Err(e) = save_atlas(&app_state.map_state, &config::current().atlas) {
+                    eprintln!("Failed to save the atlas: {e}");
+                }
+            }
+            Event::KeyDown {
+                keycode: Some(Keycode::I),
+                ..
+            } => match app_state.map_state.visible_map().read() {
+                Ok(map) => print!("{}", map.statistics()),
+                Err(e) => eprintln!("Failed to compute the statistics: {e}"),
+            },
+            Event::KeyDown {
+                keycode: Some(Keycode::F5),
+                ..
+            } => {
+                if let Err(e) = config::reload() {
+                    eprintln!("{e}");
+                }
+            }
+            Event::KeyDown {
+                keycode: Some(Keycode::U),
+                ..
+            } => {
+                app_state.map_state.toggle_layer();
+            }
+            Event::KeyDown {
+                keycode: Some(Keycode::L),
+                ..
+            } => {
+                app_state.map_state.toggle_labels();
+            }
+            Event::KeyDown {
+                keycode: Some(Keycode::N),
+                ..
+            } => {
+                // takes effect with the next generation
+                app_state.map_state.cycle_generator();
+            }
+            Event::MouseButtonDown {
+                mouse_btn: MouseButton::Right,
+                x,
+                y,
+                ..
+            } => {
+                let viewport = &app_state.viewport_state;
+                let renderer = HexRenderer::new(viewport.zoom_level);
+                let map_size = app_state.map_state.map_size;
+                if let Some(index) = renderer.hex_at((x, y), viewport.offset, map_size) {
+                    app_state.map_state.toggle_selection(index);
+                }
+            }
+            Event::KeyDown {
+                keycode: Some(Keycode::Return | Keycode::KpEnter),
+                ..
+            } => {
+                if let Err(e) = app_state.enter_region() {
+                    eprintln!("{e}");
+                }
+            }
+            Event::KeyDown {
+                keycode: Some(Keycode::Backspace),
+                ..
+            } => {
+                app_state.leave_region();
+            }
+            Event::KeyDown {
+                keycode: Some(Keycode::V),
+                ..
+            } => {
+                app_state.map_state.playback.toggle_enabled();
+            }
+            Event::KeyDown {
+                keycode: Some(Keycode::Space),
+                ..
+            } => {
+                app_state.map_state.playback.toggle_paused();
+            }
+            Event::KeyDown {
+                keycode: Some(Keycode::Period),
+                ..
+            } => {
+                app_state.map_state.playback.step();
+            }
+            Event::KeyDown {
+                keycode: Some(Keycode::G),
+                ..
+            } => {
+                app_state.map_state.playback.cycle_granularity();
+            }
+            Event::KeyDown {
+                keycode: Some(Keycode::Plus | Keycode::KpPlus | Keycode::Equals),
+                ..
+            } => {
+                app_state.map_state.playback.faster();
+            }
+            Event::KeyDown {
+                keycode: Some(Keycode::Minus | Keycode::KpMinus),
+                ..
+            } => {
+                app_state.map_state.playback.slower();
+            }
             _ => {
                 app_state.viewport_state.handle_events(event);
             }
@@ -0,0 +1,533 @@
+use crate::config::{AtlasConfig, Paper};
+use crate::map::settlements::SettlementSize;
+use crate::map::{Map, MapState};
+use crate::rendering::HexRenderer;
+use chrono::Utc;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::Write;
+
+// the hexes are computed at this radius and scaled to the size on paper (see `HexRenderer`)
+const RENDER_RADIUS: i16 = 100;
+const POINTS_PER_MM: f32 = 72f32 / 25.4;
+// height of the line with the name of the sheet above the map, in points
+const HEADER_HEIGHT: f32 = 18f32;
+// blocks of hexes across the overview on the index page
+const OVERVIEW_BLOCKS: usize = 150;
+
+// Exports the selected hexes (or the whole surface) as a PDF to be printed across several sheets
+// (see `AtlasConfig`). The first page is an index with an overview of the sheets and a legend of the
+// environments, every sheet names the sheets continuing it and marks where they overlap with it
+pub fn save_atlas(map_state: &MapState, config: &AtlasConfig) -> Result<(), String> {
+    let map = map_state.map.read().map_err(|e| e.to_string())?;
+    let (width, height) = (map_state.map_size.0 as usize, map_state.map_size.1 as usize);
+    let (min, max) = if map_state.selection.is_empty() {
+        ((0, 0), (width - 1, height - 1))
+    } else {
+        let xs = map_state.selection.iter().map(|s| s.0);
+        let ys = map_state.selection.iter().map(|s| s.1);
+        (
+            (xs.clone().min().unwrap_or(0), ys.clone().min().unwrap_or(0)),
+            (xs.max().unwrap_or(0), ys.max().unwrap_or(0)),
+        )
+    };
+
+    let renderer = HexRenderer::new(RENDER_RADIUS);
+    let scale = config.hex_size * POINTS_PER_MM / renderer.hex_width;
+    let (page_width, page_height) = match config.paper {
+        Paper::A4 => (595.28, 841.89),
+        Paper::Letter => (612f32, 792f32),
+    };
+    let margin = config.margin * POINTS_PER_MM;
+    let overlap = config.overlap * POINTS_PER_MM;
+    let content = (
+        page_width - 2f32 * margin,
+        page_height - 2f32 * margin - 2f32 * HEADER_HEIGHT,
+    );
+    if overlap * 2f32 >= content.0.min(content.1) {
+        return Err(String::from(
+            "the overlap of the sheets is too large for the paper",
+        ));
+    }
+
+    let (origin, extent) = printed_area(&renderer, scale, min, max);
+    let sheets = |extent: f32, content: f32| {
+        ((extent - overlap) / (content - overlap)).ceil().max(1f32) as usize
+    };
+    let (columns, rows) = (sheets(extent.0, content.0), sheets(extent.1, content.1));
+    let layout = Layout {
+        renderer,
+        scale,
+        origin,
+        min,
+        max,
+        page: (page_width, page_height),
+        margin,
+        overlap,
+        content,
+        sheets: (columns, rows),
+    };
+
+    let mut pages = vec![index_page(&map, &layout, map_state)];
+    for row in 0..rows {
+        for column in 0..columns {
+            pages.push(sheet_page(&map, &layout, (column, row)));
+        }
+    }
+    let path = format!("./{}_atlas.pdf", Utc::now().timestamp());
+    fs::write(&path, pdf(&pages, layout.page)?).map_err(|e| e.to_string())?;
+    eprintln!(
+        "Successfully saved atlas with {} sheets ({columns}x{rows}) to {path}",
+        columns * rows
+    );
+    Ok(())
+}
+
+// Position of the printed part of the map (from its first to its last hex) on the rendered map and
+// its size, in points. It starts at the left edge of the unshifted rows, so that the rows shifted by
+// half a hex fit in as well, regardless of whether the first row is one of them
+fn printed_area(
+    renderer: &HexRenderer,
+    scale: f32,
+    min: (usize, usize),
+    max: (usize, usize),
+) -> ((f32, f32), (f32, f32)) {
+    let left = renderer.hex_center((min.0 as i16, 0), (0, 0)).0;
+    let top = renderer.hex_center((0, min.1 as i16), (0, 0)).1;
+    let origin = (
+        (left - renderer.hex_width / 2f32) * scale,
+        (top - renderer.y_radius) * scale,
+    );
+    let extent = (
+        ((max.0 - min.0 + 1) as f32 + 0.5) * renderer.hex_width * scale,
+        ((max.1 - min.1) as f32 * renderer.tiling_height + 2f32 * renderer.y_radius) * scale,
+    );
+    (origin, extent)
+}
+
+struct Layout {
+    renderer: HexRenderer,
+    // points per pixel of the renderer
+    scale: f32,
+    // position of the printed part of the map on the rendered map, in points
+    origin: (f32, f32),
+    // the first/last printed hexes
+    min: (usize, usize),
+    max: (usize, usize),
+    page: (f32, f32),
+    margin: f32,
+    overlap: f32,
+    // size of the map on every sheet
+    content: (f32, f32),
+    sheets: (usize, usize),
+}
+
+impl Layout {
+    // position of the hex's middle point relative to the top left corner of the printed map
+    fn center(&self, index: (usize, usize)) -> (f32, f32) {
+        let (x, y) = self
+            .renderer
+            .hex_center((index.0 as i16, index.1 as i16), (0, 0));
+        (
+            x * self.scale - self.origin.0,
+            y * self.scale - self.origin.1,
+        )
+    }
+
+    // the corners clockwise from the top corner (see `HexRenderer::hex_corners`)
+    fn corners(&self, index: (usize, usize)) -> [(f32, f32); 6] {
+        let (x, y) = self.center(index);
+        let x_radius = self.renderer.hex_width / 2f32 * self.scale;
+        let y_radius = self.renderer.y_radius * self.scale;
+        let half_radius = y_radius / 2f32;
+        [
+            (x, y - y_radius),
+            (x + x_radius, y - half_radius),
+            (x + x_radius, y + half_radius),
+            (x, y + y_radius),
+            (x - x_radius, y + half_radius),
+            (x - x_radius, y - half_radius),
+        ]
+    }
+
+    // the top left corner of the part of the printed map on the sheet
+    fn sheet_origin(&self, sheet: (usize, usize)) -> (f32, f32) {
+        (
+            sheet.0 as f32 * (self.content.0 - self.overlap),
+            sheet.1 as f32 * (self.content.1 - self.overlap),
+        )
+    }
+}
+
+// e.g. `B3` for the third sheet of the second row
+fn sheet_name(sheet: (usize, usize)) -> String {
+    let mut letters = String::new();
+    let mut row = sheet.1 + 1;
+    while row > 0 {
+        letters.insert(0, (b'A' + ((row - 1) % 26) as u8) as char);
+        row = (row - 1) / 26;
+    }
+    format!("{letters}{}", sheet.0 + 1)
+}
+
+fn sheet_page(map: &Map, layout: &Layout, sheet: (usize, usize)) -> String {
+    let page_height = layout.page.1;
+    let (content_width, content_height) = layout.content;
+    let (left, top) = (layout.margin, page_height - layout.margin - HEADER_HEIGHT);
+    let (offset_x, offset_y) = layout.sheet_origin(sheet);
+    // from the printed map to the page, whose y axis points up
+    let to_page = |(x, y): (f32, f32)| (left + x - offset_x, top - (y - offset_y));
+
+    let mut page = String::new();
+    // only the hexes within the part of the map of the sheet are drawn
+    let _ = writeln!(
+        page,
+        "q {left:.2} {:.2} {content_width:.2} {content_height:.2} re W n",
+        top - content_height
+    );
+    let _ = writeln!(page, "0.3 w 0.35 0.35 0.35 RG");
+    let hex_width = layout.renderer.hex_width * layout.scale;
+    let tiling_height = layout.renderer.tiling_height * layout.scale;
+    let first_row = ((offset_y / tiling_height).floor() as usize).saturating_sub(1);
+    let last_row = ((offset_y + content_height) / tiling_height).ceil() as usize + 1;
+    let first_column = ((offset_x / hex_width).floor() as usize).saturating_sub(1);
+    let last_column = ((offset_x + content_width) / hex_width).ceil() as usize + 1;
+    for y in (layout.min.1 + first_row)..=(layout.min.1 + last_row).min(layout.max.1) {
+        for x in (layout.min.0 + first_column)..=(layout.min.0 + last_column).min(layout.max.0) {
+            let color = map.tiles[y][x].environment.color();
+            let _ = write!(page, "{} rg ", rgb(color.r, color.g, color.b));
+            for (i, &corner) in layout.corners((x, y)).iter().enumerate() {
+                let (p_x, p_y) = to_page(corner);
+                let _ = write!(
+                    page,
+                    "{p_x:.2} {p_y:.2} {} ",
+                    if i == 0 { "m" } else { "l" }
+                );
+            }
+            let _ = writeln!(page, "h B");
+        }
+    }
+
+    let radius = layout.renderer.y_radius * layout.scale;
+    let _ = writeln!(page, "0.12 0.12 0.12 rg 0.94 0.94 0.94 RG");
+    for settlement in &map.settlements {
+        let (x, y) = settlement.position;
+        if x < layout.min.0 || x > layout.max.0 || y < layout.min.1 || y > layout.max.1 {
+            continue;
+        }
+        let (c_x, c_y) = to_page(layout.center((x, y)));
+        // the same symbols as on screen (see `HexRenderer::render_settlement`)
+        let half_size = match settlement.size {
+            SettlementSize::Village => radius * 0.25,
+            SettlementSize::Town => radius * 0.35,
+            SettlementSize::City => radius * 0.45,
+        };
+        let _ = writeln!(
+            page,
+            "{:.2} {:.2} {:.2} {:.2} re B",
+            c_x - half_size,
+            c_y - half_size,
+            half_size * 2f32,
+            half_size * 2f32
+        );
+    }
+
+    // dashed lines where the neighbouring sheets begin
+    let (columns, rows) = layout.sheets;
+    let overlap = layout.overlap;
+    let bottom = top - content_height;
+    let right = left + content_width;
+    let _ = writeln!(page, "[4 3] 0 d 0.8 w 0 0 0 RG");
+    let mut continued = Vec::new();
+    if sheet.1 > 0 {
+        let y = top - overlap;
+        let _ = writeln!(page, "{left:.2} {y:.2} m {right:.2} {y:.2} l S");
+        continued.push(format!("above: {}", sheet_name((sheet.0, sheet.1 - 1))));
+    }
+    if sheet.0 > 0 {
+        let x = left + overlap;
+        let _ = writeln!(page, "{x:.2} {bottom:.2} m {x:.2} {top:.2} l S");
+        continued.push(format!("left: {}", sheet_name((sheet.0 - 1, sheet.1))));
+    }
+    if sheet.0 + 1 < columns {
+        let x = right - overlap;
+        let _ = writeln!(page, "{x:.2} {bottom:.2} m {x:.2} {top:.2} l S");
+        continued.push(format!("right: {}", sheet_name((sheet.0 + 1, sheet.1))));
+    }
+    if sheet.1 + 1 < rows {
+        let y = bottom + overlap;
+        let _ = writeln!(page, "{left:.2} {y:.2} m {right:.2} {y:.2} l S");
+        continued.push(format!("below: {}", sheet_name((sheet.0, sheet.1 + 1))));
+    }
+    let _ = writeln!(page, "Q");
+
+    let _ = writeln!(page, "0 0 0 rg");
+    let _ = writeln!(
+        page,
+        "{}",
+        text(
+            (left, top + 6f32),
+            12f32,
+            &format!(
+                "Sheet {} of {}",
+                sheet_name(sheet),
+                sheet_name((columns - 1, rows - 1))
+            )
+        )
+    );
+    let hexes = format!(
+        "hexes {}-{}, rows {}-{}",
+        layout.min.0 + first_column.min(layout.max.0 - layout.min.0),
+        (layout.min.0 + last_column).min(layout.max.0),
+        layout.min.1 + first_row.min(layout.max.1 - layout.min.1),
+        (layout.min.1 + last_row).min(layout.max.1)
+    );
+    let _ = writeln!(page, "{}", text((right - 140f32, top + 6f32), 9f32, &hexes));
+    let footer = if continued.is_empty() {
+        String::from("single sheet")
+    } else {
+        format!("continues {}", continued.join(", "))
+    };
+    let _ = writeln!(page, "{}", text((left, bottom - 14f32), 9f32, &footer));
+    page
+}
+
+// an overview of the printed map with the sheets it is split into and a legend of its environments
+fn index_page(map: &Map, layout: &Layout, map_state: &MapState) -> String {
+    let (page_width, page_height) = layout.page;
+    let left = layout.margin;
+    let mut top = page_height - layout.margin;
+    let mut page = String::new();
+    let _ = writeln!(page, "0 0 0 rg");
+    let _ = writeln!(page, "{}", text((left, top - 18f32), 18f32, "Atlas"));
+    let details = format!(
+        "seed {}, hexes {}-{}, rows {}-{}, {} sheets",
+        map_state.seed,
+        layout.min.0,
+        layout.max.0,
+        layout.min.1,
+        layout.max.1,
+        layout.sheets.0 * layout.sheets.1
+    );
+    let _ = writeln!(page, "{}", text((left, top - 34f32), 10f32, &details));
+    top -= 48f32;
+
+    // the overview is drawn in blocks of hexes, which is sufficient to find the sheets
+    let (hexes_x, hexes_y) = (
+        layout.max.0 - layout.min.0 + 1,
+        layout.max.1 - layout.min.1 + 1,
+    );
+    let block = hexes_x.max(hexes_y).div_ceil(OVERVIEW_BLOCKS).max(1);
+    let available = (
+        page_width - 2f32 * left,
+        (page_height - 2f32 * layout.margin) * 0.6,
+    );
+    let map_size = (
+        layout.content.0 + (layout.sheets.0 - 1) as f32 * (layout.content.0 - layout.overlap),
+        layout.content.1 + (layout.sheets.1 - 1) as f32 * (layout.content.1 - layout.overlap),
+    );
+    let factor = (available.0 / map_size.0).min(available.1 / map_size.1);
+    let hex_width = layout.renderer.hex_width * layout.scale;
+    let tiling_height = layout.renderer.tiling_height * layout.scale;
+    let to_page = |(x, y): (f32, f32)| (left + x * factor, top - y * factor);
+
+    let mut environments = BTreeSet::new();
+    for y in (layout.min.1..=layout.max.1).step_by(block) {
+        for x in (layout.min.0..=layout.max.0).step_by(block) {
+            let environment = map.tiles[y][x].environment;
+            environments.insert(environment);
+            let color = environment.color();
+            // the blocks cover the rows between them completely
+            let last = (
+                (x + block - 1).min(layout.max.0),
+                (y + block - 1).min(layout.max.1),
+            );
+            let (first, last) = (layout.center((x, y)), layout.center(last));
+            let (x1, y1) = to_page((first.0 - hex_width / 2f32, first.1 - tiling_height / 2f32));
+            let (x2, y2) = to_page((last.0 + hex_width / 2f32, last.1 + tiling_height / 2f32));
+            let _ = writeln!(
+                page,
+                "{} rg {x1:.2} {y2:.2} {:.2} {:.2} re f",
+                rgb(color.r, color.g, color.b),
+                x2 - x1,
+                y1 - y2
+            );
+        }
+    }
+    // the remaining environments of the printed hexes for the legend
+    for row in &map.tiles[layout.min.1..=layout.max.1] {
+        environments.extend(
+            row[layout.min.0..=layout.max.0]
+                .iter()
+                .map(|h| h.environment),
+        );
+    }
+
+    let _ = writeln!(page, "0.6 w 0 0 0 RG 0 0 0 rg");
+    for row in 0..layout.sheets.1 {
+        for column in 0..layout.sheets.0 {
+            let (x, y) = layout.sheet_origin((column, row));
+            let (x1, y1) = to_page((x, y));
+            let (width, height) = (layout.content.0 * factor, layout.content.1 * factor);
+            let _ = writeln!(
+                page,
+                "{x1:.2} {:.2} {width:.2} {height:.2} re S",
+                y1 - height
+            );
+            let name = sheet_name((column, row));
+            let _ = writeln!(page, "{}", text((x1 + 3f32, y1 - 11f32), 9f32, &name));
+        }
+    }
+    top -= map_size.1 * factor + 24f32;
+
+    let _ = writeln!(page, "{}", text((left, top), 12f32, "Legend"));
+    top -= 20f32;
+    let column_width = (page_width - 2f32 * left) / 3f32;
+    for (i, environment) in environments.iter().enumerate() {
+        let (x, y) = (
+            left + (i % 3) as f32 * column_width,
+            top - (i / 3) as f32 * 16f32,
+        );
+        let color = environment.color();
+        let _ = writeln!(
+            page,
+            "{} rg {x:.2} {y:.2} 12 10 re B 0 0 0 rg",
+            rgb(color.r, color.g, color.b)
+        );
+        let _ = writeln!(
+            page,
+            "{}",
+            text((x + 18f32, y + 1f32), 10f32, &environment.name())
+        );
+    }
+    page
+}
+
+fn rgb(r: u8, g: u8, b: u8) -> String {
+    format!(
+        "{:.3} {:.3} {:.3}",
+        r as f32 / 255f32,
+        g as f32 / 255f32,
+        b as f32 / 255f32
+    )
+}
+
+// the text in the built-in Helvetica, characters it lacks are replaced
+fn text(position: (f32, f32), size: f32, text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '\\' | '(' | ')' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            ' '..='~' => escaped.push(c),
+            _ => escaped.push('?'),
+        }
+    }
+    format!(
+        "BT /F1 {size} Tf {:.2} {:.2} Td ({escaped}) Tj ET",
+        position.0, position.1
+    )
+}
+
+// a document with the pages (their content streams) and a single font
+fn pdf(pages: &[String], size: (f32, f32)) -> Result<Vec<u8>, String> {
+    // 1: catalog, 2: page tree, 3: font, then the content stream and the page of every page
+    let page_id = |i: usize| 5 + 2 * i;
+    let mut objects: Vec<Vec<u8>> = vec![
+        b"<< /Type /Catalog /Pages 2 0 R >>".to_vec(),
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            (0..pages.len())
+                .map(|i| format!("{} 0 R", page_id(i)))
+                .collect::<Vec<_>>()
+                .join(" "),
+            pages.len()
+        )
+        .into_bytes(),
+        b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>"
+            .to_vec(),
+    ];
+    for (i, content) in pages.iter().enumerate() {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder
+            .write_all(content.as_bytes())
+            .map_err(|e| e.to_string())?;
+        let compressed = encoder.finish().map_err(|e| e.to_string())?;
+        let mut stream = format!(
+            "<< /Length {} /Filter /FlateDecode >>\nstream\n",
+            compressed.len()
+        )
+        .into_bytes();
+        stream.extend(compressed);
+        stream.extend(b"\nendstream");
+        objects.push(stream);
+        objects.push(
+            format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {:.2} {:.2}] \
+                /Resources << /Font << /F1 3 0 R >> >> /Contents {} 0 R >>",
+                size.0,
+                size.1,
+                page_id(i) - 1
+            )
+            .into_bytes(),
+        );
+    }
+
+    let mut bytes = b"%PDF-1.4\n".to_vec();
+    let mut offsets = Vec::with_capacity(objects.len());
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(bytes.len());
+        bytes.extend(format!("{} 0 obj\n", i + 1).into_bytes());
+        bytes.extend(object);
+        bytes.extend(b"\nendobj\n");
+    }
+    let xref = bytes.len();
+    bytes.extend(format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).into_bytes());
+    for offset in offsets {
+        bytes.extend(format!("{offset:010} 00000 n \n").into_bytes());
+    }
+    bytes.extend(
+        format!(
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{xref}\n%%EOF\n",
+            objects.len() + 1
+        )
+        .into_bytes(),
+    );
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hexes_of_all_rows_lie_within_the_printed_area() {
+        let renderer = HexRenderer::new(RENDER_RADIUS);
+        let (min, max) = ((2, 3), (6, 8));
+        let (origin, extent) = printed_area(&renderer, 0.5, min, max);
+        let layout = Layout {
+            renderer,
+            scale: 0.5,
+            origin,
+            min,
+            max,
+            page: (595.28, 841.89),
+            margin: 0f32,
+            overlap: 0f32,
+            content: extent,
+            sheets: (1, 1),
+        };
+        // the first row is shifted by half a hex, the next one is not
+        for index in [min, (min.0, min.1 + 1), max, (max.0, max.1 - 1)] {
+            for (x, y) in layout.corners(index) {
+                assert!(x >= -0.01 && x <= extent.0 + 0.01, "{index:?} at x {x}");
+                assert!(y >= -0.01 && y <= extent.1 + 0.01, "{index:?} at y {y}");
+            }
+        }
+    }
+}
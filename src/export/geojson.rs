@@ -0,0 +1,201 @@
+use crate::config::GeoJsonConfig;
+use crate::map::areas::{flood_fill, Areas};
+use crate::map::grid;
+use crate::map::settlements::Settlement;
+use crate::map::{Hex, MapState};
+use crate::rendering::HexRenderer;
+use chrono::Utc;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::BufWriter;
+
+// distance from the middle point of a hex to its top corner, in units of the exported coordinates
+const HEX_RADIUS: i16 = 10;
+
+// a corner of a hex in half hex widths/quarter hex heights from the top left corner of the map, so
+// that corners shared by neighbours are exactly equal
+type Corner = (i64, i64);
+
+// Exports the surface as GeoJSON, either every hex as a polygon of its own or the contiguous hexes
+// of the same environment merged into a single polygon (see `GeoJsonConfig`). The coordinates are
+// the pixels of the rendered map (see `HexRenderer`) with the y axis pointing north
+pub fn save_geojson(map_state: &MapState, config: &GeoJsonConfig) -> Result<(), String> {
+    let map = map_state.map.read().map_err(|e| e.to_string())?;
+    let renderer = HexRenderer::new(HEX_RADIUS);
+    let settlements: HashMap<(usize, usize), &Settlement> =
+        map.settlements.iter().map(|s| (s.position, s)).collect();
+
+    let features = if config.dissolve {
+        region_features(&map.tiles, &map.areas, &settlements, &renderer)
+    } else {
+        hex_features(&map.tiles, &settlements, &renderer)
+    };
+    let collection = json!({ "type": "FeatureCollection", "features": features });
+
+    let path = format!("./{}.geojson", Utc::now().timestamp());
+    let file = File::create(&path).map_err(|e| e.to_string())?;
+    serde_json::to_writer(BufWriter::new(file), &collection).map_err(|e| e.to_string())?;
+    eprintln!("Successfully saved GeoJSON to {path}");
+    Ok(())
+}
+
+fn hex_features(
+    tiles: &[Vec<Hex>],
+    settlements: &HashMap<(usize, usize), &Settlement>,
+    renderer: &HexRenderer,
+) -> Vec<Value> {
+    let mut features = Vec::new();
+    for (y, row) in tiles.iter().enumerate() {
+        for (x, hex) in row.iter().enumerate() {
+            let ring: Vec<Corner> = corners((x, y)).to_vec();
+            features.push(json!({
+                "type": "Feature",
+                "geometry": { "type": "Polygon", "coordinates": [coordinates(&ring, renderer)] },
+                "properties": {
+                    "x": x,
+                    "y": y,
+                    "environment": hex.environment.key(),
+                    "elevation": hex.elevation,
+                    "settlement": settlements.get(&(x, y)).map(|&s| settlement(s)),
+                },
+            }));
+        }
+    }
+    features
+}
+
+// the contiguous hexes of the same environment, which don't continue across the left/right edge of
+// the map so that their polygons don't span all of it
+fn region_features(
+    tiles: &[Vec<Hex>],
+    areas: &Areas,
+    settlements: &HashMap<(usize, usize), &Settlement>,
+    renderer: &HexRenderer,
+) -> Vec<Value> {
+    let dimensions = (tiles[0].len(), tiles.len());
+    let width = dimensions.0;
+    let neighbour = |index: (usize, usize), direction: u8| {
+        grid::neighbour(index, direction, dimensions).filter(|&(n_x, _)| n_x.abs_diff(index.0) <= 1)
+    };
+
+    let (regions, groups) = flood_fill(tiles, false, |a, b| a.environment == b.environment);
+    let mut features = Vec::new();
+    for (id, hexes) in (0..).zip(groups) {
+        let (start_x, start_y) = hexes[0];
+        let environment = tiles[start_y][start_x].environment;
+        // the edges towards other regions, running clockwise around each hex (on screen)
+        let mut edges: BTreeMap<Corner, Corner> = BTreeMap::new();
+        for &index in &hexes {
+            let hex_corners = corners(index);
+            for direction in grid::DIRECTIONS {
+                let inside = neighbour(index, direction)
+                    .is_some_and(|(n_x, n_y)| regions[n_y * width + n_x] == id);
+                if !inside {
+                    let start = hex_corners[(direction as usize + 5) % 6];
+                    edges.insert(start, hex_corners[direction as usize]);
+                }
+            }
+        }
+        // every corner has at most one outgoing edge, so the edges form closed rings
+        let mut rings = Vec::new();
+        while let Some((&start, _)) = edges.iter().next() {
+            let mut ring = vec![start];
+            let mut corner = start;
+            while let Some(next) = edges.remove(&corner) {
+                if next != start {
+                    ring.push(next);
+                }
+                corner = next;
+            }
+            rings.push(ring);
+        }
+        // the outer boundary encloses all other rings (the holes)
+        rings.sort_by_key(|ring| std::cmp::Reverse(area(ring)));
+
+        let elevations: Vec<i64> = hexes
+            .iter()
+            .map(|&(x, y)| tiles[y][x].elevation as i64)
+            .collect();
+        let mut region_settlements: Vec<&Settlement> = hexes
+            .iter()
+            .filter_map(|index| settlements.get(index).copied())
+            .collect();
+        region_settlements.sort_by_key(|s| s.position);
+        features.push(json!({
+            "type": "Feature",
+            "geometry": {
+                "type": "Polygon",
+                "coordinates": rings
+                    .iter()
+                    .map(|ring| coordinates(ring, renderer))
+                    .collect::<Vec<_>>(),
+            },
+            "properties": {
+                // the first hex of the region (row by row)
+                "x": start_x,
+                "y": start_y,
+                "environment": environment.key(),
+                // see `names::name`, regions across the left/right edge of the map are split
+                // into two with the same name
+                "name": areas.environment_at((start_x, start_y)).and_then(|a| a.name.as_ref()),
+                "hexes": hexes.len(),
+                "min_elevation": elevations.iter().min(),
+                "max_elevation": elevations.iter().max(),
+                "mean_elevation": elevations.iter().sum::<i64>() / elevations.len() as i64,
+                "settlements": region_settlements
+                    .into_iter()
+                    .map(settlement)
+                    .collect::<Vec<_>>(),
+            },
+        }));
+    }
+    features
+}
+
+fn settlement(settlement: &Settlement) -> Value {
+    json!({
+        "x": settlement.position.0,
+        "y": settlement.position.1,
+        "name": settlement.name,
+        "size": format!("{:?}", settlement.size),
+        "level": settlement.level,
+        "population": settlement.population,
+    })
+}
+
+// the corners of the hex clockwise (on screen) from its top corner (see `HexRenderer::hex_corners`)
+fn corners(index: (usize, usize)) -> [Corner; 6] {
+    let (x, y) = (index.0 as i64, index.1 as i64);
+    let (c_x, c_y) = (2 * x + 1 + y % 2, 3 * y + 2);
+    [
+        (c_x, c_y - 2),
+        (c_x + 1, c_y - 1),
+        (c_x + 1, c_y + 1),
+        (c_x, c_y + 2),
+        (c_x - 1, c_y + 1),
+        (c_x - 1, c_y - 1),
+    ]
+}
+
+// twice the area enclosed by the ring
+fn area(ring: &[Corner]) -> i64 {
+    let next = ring.iter().cycle().skip(1);
+    ring.iter()
+        .zip(next)
+        .map(|(a, b)| a.0 * b.1 - b.0 * a.1)
+        .sum::<i64>()
+        .abs()
+}
+
+// the closed ring in exported coordinates, reversed since GeoJSON expects counterclockwise outer
+// boundaries (and clockwise holes)
+fn coordinates(ring: &[Corner], renderer: &HexRenderer) -> Vec<[f64; 2]> {
+    let (unit_x, unit_y) = (renderer.hex_width / 2f32, renderer.y_radius / 2f32);
+    let round = |value: f32| (value as f64 * 100f64).round() / 100f64;
+    ring.first()
+        .into_iter()
+        .chain(ring.iter().rev())
+        .map(|&(x, y)| [round(x as f32 * unit_x), round(-(y as f32) * unit_y)])
+        .collect()
+}
@@ -0,0 +1,21 @@
+// Exports the map into the file formats of other tools
+pub mod atlas;
+pub mod geojson;
+pub mod tmx;
+pub mod worldographer;
+
+// the text with the characters that have a meaning in XML replaced by entities
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,183 @@
+use crate::export::escape_xml;
+use crate::map::environments::Environment;
+use crate::map::{Map, MapState};
+use crate::rendering::HexRenderer;
+use chrono::Utc;
+use image::{Rgba, RgbaImage};
+use std::fmt::Write as _;
+use std::fs;
+
+// distance from the middle point of a tile to its top corner, in pixels
+const TILE_RADIUS: i16 = 16;
+// tiles per row of the tileset image
+const TILESET_COLUMNS: usize = 8;
+
+// Exports the map as a Tiled map (`.tmx`) with hexagonal pointy-topped tiles, whose odd rows are
+// shifted to the right like the rendered map. Every environment is a tile of the tileset, which is
+// generated from their colours as an image next to the map. The surface and the underground are
+// layers of their own, settlements are point objects
+pub fn save_tmx(map_state: &MapState) -> Result<(), String> {
+    let surface = map_state.map.read().map_err(|e| e.to_string())?;
+    let underground = map_state.underground.read().map_err(|e| e.to_string())?;
+    let (width, height) = map_state.map_size;
+
+    // the same geometry as the rendered hexes, rounded to whole pixels as Tiled requires
+    let renderer = HexRenderer::new(TILE_RADIUS);
+    let tile_width = renderer.hex_width.round() as u32;
+    let tile_height = TILE_RADIUS as u32 * 2;
+    let side_length = TILE_RADIUS as u32;
+    let environments = Environment::all();
+
+    let timestamp = Utc::now().timestamp();
+    let tileset_path = format!("{timestamp}_tileset.png");
+    let tileset = tileset_image(&environments, (tile_width, tile_height, side_length));
+    tileset
+        .save(format!("./{tileset_path}"))
+        .map_err(|e| e.to_string())?;
+
+    // writing to a `String` cannot fail
+    let mut xml = String::new();
+    let _ = writeln!(xml, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>");
+    let _ = writeln!(
+        xml,
+        "<map version=\"1.10\" orientation=\"hexagonal\" renderorder=\"right-down\" \
+        width=\"{width}\" height=\"{height}\" tilewidth=\"{tile_width}\" \
+        tileheight=\"{tile_height}\" hexsidelength=\"{side_length}\" staggeraxis=\"y\" \
+        staggerindex=\"odd\" infinite=\"0\" nextlayerid=\"4\" nextobjectid=\"{}\">",
+        surface.settlements.len() + 1
+    );
+    let _ = writeln!(
+        xml,
+        " <tileset firstgid=\"1\" name=\"environments\" tilewidth=\"{tile_width}\" \
+        tileheight=\"{tile_height}\" tilecount=\"{}\" columns=\"{TILESET_COLUMNS}\">",
+        environments.len()
+    );
+    let _ = writeln!(
+        xml,
+        "  <image source=\"{tileset_path}\" width=\"{}\" height=\"{}\"/>",
+        tileset.width(),
+        tileset.height()
+    );
+    for environment in &environments {
+        let _ = writeln!(
+            xml,
+            "  <tile id=\"{}\"><properties><property name=\"environment\" value=\"{}\"/>\
+            </properties></tile>",
+            environment.id(),
+            escape_xml(&environment.key())
+        );
+    }
+    let _ = writeln!(xml, " </tileset>");
+    write_layer(&mut xml, 1, "surface", &surface, true);
+    write_layer(&mut xml, 2, "underground", &underground, false);
+
+    let _ = writeln!(xml, " <objectgroup id=\"3\" name=\"settlements\">");
+    for (i, settlement) in surface.settlements.iter().enumerate() {
+        let (p_x, p_y) = tile_center(settlement.position, (tile_width, tile_height, side_length));
+        let _ = writeln!(
+            xml,
+            "  <object id=\"{}\" name=\"{:?}\" type=\"settlement\" x=\"{p_x:.1}\" y=\"{p_y:.1}\">\
+            <properties><property name=\"level\" type=\"int\" value=\"{}\"/>\
+            <property name=\"population\" type=\"int\" value=\"{}\"/></properties><point/></object>",
+            i + 1,
+            settlement.size,
+            settlement.level,
+            settlement.population
+        );
+    }
+    let _ = writeln!(xml, " </objectgroup>");
+    let _ = writeln!(xml, "</map>");
+
+    let path = format!("./{timestamp}.tmx");
+    fs::write(&path, xml).map_err(|e| e.to_string())?;
+    eprintln!("Successfully saved Tiled map to {path}");
+    Ok(())
+}
+
+// the middle point of the tile in pixels of the Tiled map, which places the tiles by their rounded
+// size rather than the exact geometry of the rendered hexes
+fn tile_center(index: (usize, usize), tile: (u32, u32, u32)) -> (f32, f32) {
+    let (tile_width, tile_height, side_length) = tile;
+    let (x, y) = index;
+    let row_offset = (y % 2) as f32 * tile_width as f32 / 2f32;
+    let row_height = (tile_height + side_length) as f32 / 2f32;
+    (
+        (x as f32 + 0.5) * tile_width as f32 + row_offset,
+        y as f32 * row_height + tile_height as f32 / 2f32,
+    )
+}
+
+// the tiles of the layer as CSV, the id of every tile is the id of its environment
+fn write_layer(xml: &mut String, id: u32, name: &str, map: &Map, visible: bool) {
+    let width = map.tiles.first().map_or(0, |row| row.len());
+    let _ = writeln!(
+        xml,
+        " <layer id=\"{id}\" name=\"{name}\" width=\"{width}\" height=\"{}\" visible=\"{}\">",
+        map.tiles.len(),
+        visible as u8
+    );
+    let _ = writeln!(xml, "  <data encoding=\"csv\">");
+    let rows: Vec<String> = map
+        .tiles
+        .iter()
+        .map(|row| {
+            row.iter()
+                // global ids start at 1, 0 is an empty tile
+                .map(|hex| (hex.environment.id() as u32 + 1).to_string())
+                .collect::<Vec<_>>()
+                .join(",")
+        })
+        .collect();
+    let _ = writeln!(xml, "{}", rows.join(",\n"));
+    let _ = writeln!(xml, "  </data>");
+    let _ = writeln!(xml, " </layer>");
+}
+
+// a hex filled with the colour of every environment (in the order of their ids), the rest of the
+// tiles is transparent
+fn tileset_image(environments: &[Environment], tile: (u32, u32, u32)) -> RgbaImage {
+    let (tile_width, tile_height, side_length) = tile;
+    let rows = environments.len().div_ceil(TILESET_COLUMNS);
+    let mut image = RgbaImage::new(
+        tile_width * TILESET_COLUMNS as u32,
+        tile_height * rows.max(1) as u32,
+    );
+    let (half_width, half_height) = (tile_width as f32 / 2f32, tile_height as f32 / 2f32);
+    let half_side = side_length as f32 / 2f32;
+
+    for (i, environment) in environments.iter().enumerate() {
+        let color = environment.color();
+        let origin = (
+            (i % TILESET_COLUMNS) as u32 * tile_width,
+            (i / TILESET_COLUMNS) as u32 * tile_height,
+        );
+        for y in 0..tile_height {
+            for x in 0..tile_width {
+                // distances of the middle of the pixel from the middle point of the tile
+                let d_x = (x as f32 + 0.5 - half_width).abs();
+                let d_y = (y as f32 + 0.5 - half_height).abs();
+                // the slanted edges run from the top corner to the ends of the vertical sides
+                let max_y = half_side + (half_height - half_side) * (1f32 - d_x / half_width);
+                if d_x <= half_width && d_y <= max_y {
+                    let pixel = Rgba([color.r, color.g, color.b, 255]);
+                    image.put_pixel(origin.0 + x, origin.1 + y, pixel);
+                }
+            }
+        }
+    }
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn objects_are_placed_on_the_rounded_tiles() {
+        // the tiles of the export: 28 pixels wide, rows 24 pixels apart
+        let tile = (28, 32, 16);
+        assert_eq!(tile_center((0, 0), tile), (14.0, 16.0));
+        assert_eq!(tile_center((3, 1), tile), (112.0, 40.0));
+        assert_eq!(tile_center((3, 10), tile), (98.0, 256.0));
+    }
+}
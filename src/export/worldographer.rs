@@ -0,0 +1,154 @@
+use crate::config::WorldographerConfig;
+use crate::export::escape_xml;
+use crate::map::environments::Environment;
+use crate::map::grid;
+use crate::map::names::LabelKind;
+use crate::map::settlements::SettlementSize;
+use crate::map::MapState;
+use chrono::Utc;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::Write;
+
+// width of a hex in pixels of the tool, its height follows from it
+const HEX_WIDTH: f32 = 46.0;
+// version of the file format the export is written in
+const FORMAT_VERSION: &str = "1.74";
+
+// Exports the surface as a Worldographer map (`.wxx`), which is gzipped XML encoded as UTF-16.
+// The hexes are stored column by column with their terrain type and elevation, settlements become
+// features with a label and the other names of the map become labels. Rivers, roads and the
+// underground are not exported
+pub fn save_worldographer(
+    map_state: &MapState,
+    config: &WorldographerConfig,
+) -> Result<(), String> {
+    let map = map_state.map.read().map_err(|e| e.to_string())?;
+    let (width, height) = (map_state.map_size.0 as usize, map_state.map_size.1 as usize);
+
+    // index 0 is the blank terrain of the tool, every other terrain type is listed once
+    let names: BTreeSet<&String> = config.terrain.values().collect();
+    let indices: HashMap<&String, usize> =
+        names.iter().enumerate().map(|(i, &n)| (n, i + 1)).collect();
+    let terrain = |environment: Environment| {
+        config
+            .terrain
+            .get(&environment)
+            .map_or(0, |name| indices[name])
+    };
+
+    // writing to a `String` cannot fail
+    let mut xml = String::new();
+    let hex_height = HEX_WIDTH * 2f32 * grid::CORNER_RADIUS;
+    let _ = writeln!(xml, "<?xml version='1.1' encoding='utf-16'?>");
+    let _ = writeln!(
+        xml,
+        "<map type=\"WORLD\" version=\"{FORMAT_VERSION}\" lastViewLevel=\"WORLD\" \
+        hexWidth=\"{HEX_WIDTH:.1}\" hexHeight=\"{hex_height:.1}\" hexOrientation=\"ROWS\" \
+        mapProjection=\"FLAT\" showNotes=\"true\" showGMOnly=\"false\" showFeatureLabels=\"true\" \
+        showGrid=\"true\" showGridNumbers=\"false\" showShadows=\"true\">"
+    );
+    let terrain_map: Vec<String> = std::iter::once(String::from("Blank\t0"))
+        .chain(
+            names
+                .iter()
+                .map(|n| format!("{}\t{}", escape_xml(n), indices[n])),
+        )
+        .collect();
+    let _ = writeln!(xml, "<terrainmap>{}</terrainmap>", terrain_map.join("\t"));
+    let _ = writeln!(xml, "<maplayer name=\"Labels\" isVisible=\"true\"/>");
+    let _ = writeln!(xml, "<maplayer name=\"Above Terrain\" isVisible=\"true\"/>");
+    let _ = writeln!(xml, "<maplayer name=\"Terrain Land\" isVisible=\"true\"/>");
+
+    let _ = writeln!(
+        xml,
+        "<tiles viewLevel=\"WORLD\" tilesWide=\"{width}\" tilesHigh=\"{height}\">"
+    );
+    for x in 0..width {
+        let _ = writeln!(xml, "<tilerow>");
+        for row in map.tiles.iter() {
+            let hex = row[x];
+            // terrain, elevation, icy, GM only and the 7 resources, `Z` marks the end of the tile
+            let _ = writeln!(
+                xml,
+                "{}\t{}.0\t0\t0\t0\t0\t0\t0\t0\t0\t0\tZ",
+                terrain(hex.environment),
+                hex.elevation
+            );
+        }
+        let _ = writeln!(xml, "</tilerow>");
+    }
+    let _ = writeln!(xml, "</tiles>");
+
+    let _ = writeln!(xml, "<features>");
+    for settlement in &map.settlements {
+        let feature = match settlement.size {
+            SettlementSize::Village => &config.features.village,
+            SettlementSize::Town => &config.features.town,
+            SettlementSize::City => &config.features.city,
+        };
+        let (p_x, p_y) = hex_center(settlement.position);
+        let label = match &settlement.name {
+            Some(name) => format!("{name} (level {})", settlement.level),
+            None => format!("{:?} (level {})", settlement.size, settlement.level),
+        };
+        let _ = writeln!(
+            xml,
+            "<feature type=\"{}\" rotate=\"0.0\" mapLayer=\"Above Terrain\" scale=\"-1.0\" \
+            labelPosition=\"6:00\" isWorld=\"true\" isGMOnly=\"false\" isPlaceFreely=\"false\">\
+            <location viewLevel=\"WORLD\" x=\"{p_x:.1}\" y=\"{p_y:.1}\"/>\
+            <label mapLayer=\"Labels\" isWorld=\"true\" isGMOnly=\"false\">\
+            <location viewLevel=\"WORLD\" x=\"{p_x:.1}\" y=\"{p_y:.1}\" scale=\"12.5\"/>{}</label>\
+            </feature>",
+            escape_xml(feature),
+            escape_xml(&label)
+        );
+    }
+    let _ = writeln!(xml, "</features>");
+    let _ = writeln!(xml, "<labels>");
+    for label in &map.labels {
+        let scale = match label.kind {
+            // the settlements are labelled as features already
+            LabelKind::Settlement(_) => continue,
+            LabelKind::Landmass | LabelKind::Water => 25.0,
+            LabelKind::Environment | LabelKind::River => 12.5,
+        };
+        let (p_x, p_y) = hex_center(label.position);
+        let _ = writeln!(
+            xml,
+            "<label mapLayer=\"Labels\" isWorld=\"true\" isGMOnly=\"false\">\
+            <location viewLevel=\"WORLD\" x=\"{p_x:.1}\" y=\"{p_y:.1}\" scale=\"{scale:.1}\"/>{}</label>",
+            escape_xml(&label.text)
+        );
+    }
+    let _ = writeln!(xml, "</labels>");
+    let _ = writeln!(xml, "<shapes/>");
+    let _ = writeln!(xml, "<notes/>");
+    let _ = writeln!(xml, "</map>");
+
+    // UTF-16 as written by Java: big endian with a byte order mark
+    let bytes: Vec<u8> = std::iter::once(0xfeff)
+        .chain(xml.encode_utf16())
+        .flat_map(u16::to_be_bytes)
+        .collect();
+    let path = format!("./{}.wxx", Utc::now().timestamp());
+    let file = File::create(&path).map_err(|e| e.to_string())?;
+    let mut encoder = GzEncoder::new(file, Compression::default());
+    encoder.write_all(&bytes).map_err(|e| e.to_string())?;
+    encoder.finish().map_err(|e| e.to_string())?;
+    eprintln!("Successfully saved Worldographer map to {path}");
+    Ok(())
+}
+
+// the middle point of the hex in pixels of the tool from the top left corner of the map, where
+// features and labels are positioned
+fn hex_center(index: (usize, usize)) -> (f32, f32) {
+    let (c_x, c_y) = grid::center(index);
+    (
+        (c_x + 0.5) * HEX_WIDTH,
+        (c_y + grid::CORNER_RADIUS) * HEX_WIDTH,
+    )
+}
@@ -1,3 +1,4 @@
+use crate::config::HeightmapConfig;
 use crate::map::MapState;
 use crate::rendering::HexRenderer;
 use chrono::Utc;
@@ -12,7 +13,7 @@ pub fn save_as_png(map_state: &MapState, hex_radius: i16) -> Result<(), String>
     let pixel_format = PixelFormatEnum::RGBA8888;
     let renderer = HexRenderer::new(hex_radius);
     let (width, height) = renderer.get_bounds(map_state.map_size);
-    println!(
+    eprintln!(
         "Exporting image with dimensions {:?}, depending on the size and resolution,\
         this might take a while and the application might also be marked as not responding!",
         (width, height),
@@ -35,6 +36,22 @@ pub fn save_as_png(map_state: &MapState, hex_radius: i16) -> Result<(), String>
     )?;
 
     surface.save(format!("./{}.png", Utc::now().timestamp()))?;
-    println!("Successfully saved image");
+    eprintln!("Successfully saved image");
+    Ok(())
+}
+
+// exports the elevation of the surface as a 16 bit greyscale image with a pixel per hex, which the
+// heightmap generator can import again (see `HeightmapConfig`)
+pub fn save_heightmap(map_state: &MapState, config: &HeightmapConfig) -> Result<(), String> {
+    let map = map_state.map.read().map_err(|e| e.to_string())?;
+    let (width, height) = (map_state.map_size.0 as u32, map_state.map_size.1 as u32);
+    let image =
+        ::image::ImageBuffer::<::image::Luma<u16>, Vec<u16>>::from_fn(width, height, |x, y| {
+            ::image::Luma([config.to_grey(map.tiles[y as usize][x as usize].elevation)])
+        });
+
+    let path = format!("./{}_heightmap.png", Utc::now().timestamp());
+    image.save(&path).map_err(|e| e.to_string())?;
+    eprintln!("Successfully saved heightmap to {path}");
     Ok(())
 }
@@ -1,5 +1,7 @@
 pub mod app_state;
+pub mod config;
 pub mod events;
+pub mod export;
 pub mod image;
 pub mod map;
 pub mod rendering;
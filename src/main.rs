@@ -1,6 +1,7 @@
 use pf2e_terrain_gen::app_state::AppState;
+use pf2e_terrain_gen::config;
 use pf2e_terrain_gen::events::handle_events;
-use pf2e_terrain_gen::map::MapState;
+use pf2e_terrain_gen::map::{GeneratorKind, MapSettings, MapState};
 use pf2e_terrain_gen::rendering::HexRenderer;
 use pf2e_terrain_gen::viewport::ViewPortState;
 use sdl2::pixels::Color;
@@ -13,12 +14,22 @@ const SCREEN_HEIGHT: u32 = 720;
 const WINDOW_TITLE: &str = "PF2e Terrain Generator";
 // roughly earth sized: 2076
 const MAP_SIZE: (u16, u16) = (1500, 1500);
-const SMOOTHING_ITERATIONS: u16 = 0; //5;
+const SMOOTHING_ITERATIONS: u16 = 3;
+// chance in percent for a hex to adapt to its neighbours in each smoothing iteration, since water
+// is the most common environment, higher values/more iterations lead to less and less land
+const SMOOTHING_STRENGTH: u8 = 40;
+// more iterations carve deeper valleys but take longer
+const EROSION_ITERATIONS: u16 = 10;
+// fixed seed to reproduce a specific map, a random one is used if `None`
+const MAP_SEED: Option<u64> = None;
 
 fn main() -> Result<(), String> {
     let (mut event_pump, mut canvas) = show_window()?;
 
-    // TODO: Maybe add option to intentionally slow down generation to be able to see the steps more easily
+    // the built-in default config is used if the file cannot be loaded
+    if let Err(e) = config::reload() {
+        eprintln!("{e}");
+    }
 
     // TODO: Infinite Scrolling/Wrap-around effect (only in horizontal direction)
 
@@ -30,11 +41,21 @@ fn main() -> Result<(), String> {
     // TODO: MAYBE Randomly generate elevation (highs/lows less likely?), smooth elevation & color depending on elevation
 
     let mut app_state = AppState {
-        map_state: MapState::new((MAP_SIZE.0, MAP_SIZE.1), SMOOTHING_ITERATIONS)?,
+        map_state: MapState::new(MapSettings {
+            dimensions: MAP_SIZE,
+            iterations: SMOOTHING_ITERATIONS,
+            erosion_iterations: EROSION_ITERATIONS,
+            smoothing_strength: SMOOTHING_STRENGTH,
+            seed: MAP_SEED,
+            generator: GeneratorKind::Procedural,
+        })?,
         viewport_state: ViewPortState::new(),
+        parents: Vec::new(),
     };
 
     loop {
+        config::reload_if_changed();
+
         let quit = handle_events(&mut event_pump, &mut app_state)?;
         if quit {
             break;
@@ -0,0 +1,337 @@
+use crate::map::chunks::snapshot;
+use crate::map::environments::Environment;
+use crate::map::{grid, Hex, Map};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, RwLock};
+
+// Contiguous hexes that are referred to as a whole, e.g. to name them. Not to be confused with
+// regions, which are maps of a few hexes at a finer scale
+#[derive(Clone, Debug)]
+pub struct Area {
+    // stable for the same map, areas are numbered in the order of their first hex (row by row)
+    pub id: u32,
+    pub kind: AreaKind,
+    pub size: usize,
+    // the first hex of the area (row by row)
+    pub first: (usize, usize),
+    // the top left and bottom right corner of the bounding box, the left corner lies further right
+    // than the right corner if the area continues across the left/right edge of the map
+    pub min: (usize, usize),
+    pub max: (usize, usize),
+    // the hex furthest away from the border of the area, e.g. to place its label
+    pub centre: (usize, usize),
+    // see `names::name`, areas that are too small to stand out remain unnamed
+    pub name: Option<String>,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum AreaKind {
+    Landmass,
+    // see `UnderseaConfig::max_lake_size`
+    Sea,
+    Lake,
+    // contiguous hexes of the same environment
+    Environment(Environment),
+}
+
+// Every hex belongs to a landmass, sea or lake (its body) and to the area of its environment
+#[derive(Default)]
+pub struct Areas {
+    // the bodies first, then the environment areas, indexed by their id
+    pub areas: Vec<Area>,
+    width: usize,
+    // the ids of the areas of every hex, row by row
+    bodies: Vec<u32>,
+    environments: Vec<u32>,
+}
+
+impl Areas {
+    pub fn get(&self, id: u32) -> Option<&Area> {
+        self.areas.get(id as usize)
+    }
+
+    // the landmass, sea or lake of the hex
+    pub fn body_at(&self, index: (usize, usize)) -> Option<&Area> {
+        let id = self.bodies.get(index.1 * self.width + index.0)?;
+        self.get(*id)
+    }
+
+    // the contiguous hexes of the environment of the hex
+    pub fn environment_at(&self, index: (usize, usize)) -> Option<&Area> {
+        let id = self.environments.get(index.1 * self.width + index.0)?;
+        self.get(*id)
+    }
+
+    pub fn of_kind(&self, kind: AreaKind) -> impl Iterator<Item = &Area> {
+        self.areas.iter().filter(move |area| area.kind == kind)
+    }
+}
+
+// Finds the landmasses, seas, lakes and environment areas of the map (which continue across the
+// left/right edge of the map like it wraps)
+pub fn detect(map: &Arc<RwLock<Map>>, max_lake_size: u32) -> Result<(), String> {
+    let tiles = snapshot(map)?;
+    let areas = find_areas(&tiles, max_lake_size);
+    let count = |kind| areas.of_kind(kind).count();
+    eprintln!(
+        "Detected {} landmasses, {} seas, {} lakes and {} environment areas",
+        count(AreaKind::Landmass),
+        count(AreaKind::Sea),
+        count(AreaKind::Lake),
+        areas
+            .areas
+            .iter()
+            .filter(|a| matches!(a.kind, AreaKind::Environment(_)))
+            .count()
+    );
+    map.write().map_err(|e| e.to_string())?.areas = areas;
+    Ok(())
+}
+
+// Finds the areas of the tiles, see `detect`
+pub(crate) fn find_areas(tiles: &[Vec<Hex>], max_lake_size: u32) -> Areas {
+    let width = tiles.first().map_or(0, |row| row.len());
+    let mut areas = Vec::new();
+    let bodies = label(tiles, &mut areas, |a, b| {
+        a.environment.is_water() == b.environment.is_water()
+    });
+    for area in &mut areas {
+        let (x, y) = area.first;
+        area.kind = body_kind(&tiles[y][x], area.size, max_lake_size);
+    }
+    let environments = label(tiles, &mut areas, |a, b| a.environment == b.environment);
+    Areas {
+        areas,
+        width,
+        bodies,
+        environments,
+    }
+}
+
+// marks every water hex that belongs to a lake rather than the sea (see `AreaKind`)
+pub fn lakes(tiles: &[Vec<Hex>], max_lake_size: u32) -> Vec<Vec<bool>> {
+    let width = tiles.first().map_or(0, |row| row.len());
+    let (ids, groups) = flood_fill(tiles, true, |a, b| {
+        a.environment.is_water() == b.environment.is_water()
+    });
+    tiles
+        .iter()
+        .enumerate()
+        .map(|(y, row)| {
+            row.iter()
+                .enumerate()
+                .map(|(x, hex)| {
+                    let size = groups[ids[y * width + x] as usize].len();
+                    body_kind(hex, size, max_lake_size) == AreaKind::Lake
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// whether the contiguous land or water hexes of the given size around the hex are a landmass, sea
+// or lake
+fn body_kind(hex: &Hex, size: usize, max_lake_size: u32) -> AreaKind {
+    if !hex.environment.is_water() {
+        AreaKind::Landmass
+    } else if size <= max_lake_size as usize {
+        AreaKind::Lake
+    } else {
+        AreaKind::Sea
+    }
+}
+
+// the ids of the areas of every hex (row by row), adding an area for every group of contiguous hexes
+// that belong together
+fn label<F: Fn(&Hex, &Hex) -> bool>(
+    tiles: &[Vec<Hex>],
+    areas: &mut Vec<Area>,
+    together: F,
+) -> Vec<u32> {
+    let height = tiles.len();
+    let width = tiles.first().map_or(0, |row| row.len());
+    let offset = areas.len() as u32;
+    let (mut ids, groups) = flood_fill(tiles, true, together);
+    for id in &mut ids {
+        *id += offset;
+    }
+    for (id, hexes) in (offset..).zip(&groups) {
+        let first = hexes[0];
+        let (min, max) = bounds(hexes, width);
+        areas.push(Area {
+            id,
+            kind: AreaKind::Environment(tiles[first.1][first.0].environment),
+            size: hexes.len(),
+            first,
+            min,
+            max,
+            centre: centre(hexes, &ids, id, (width, height)),
+            name: None,
+        });
+    }
+    ids
+}
+
+// Groups the contiguous hexes that belong together (compared to the first hex of the group),
+// numbered in the order of their first hex (row by row). Returns the group of every hex (row by
+// row) and the hexes of every group, starting with its first hex. Groups continue across the
+// left/right edge of the map only if it `wraps`
+pub fn flood_fill<F: Fn(&Hex, &Hex) -> bool>(
+    tiles: &[Vec<Hex>],
+    wraps: bool,
+    together: F,
+) -> (Vec<u32>, Vec<Vec<(usize, usize)>>) {
+    let height = tiles.len();
+    let width = tiles.first().map_or(0, |row| row.len());
+    let mut ids = vec![u32::MAX; width * height];
+    let mut groups = Vec::new();
+    for y in 0..height {
+        for x in 0..width {
+            if ids[y * width + x] != u32::MAX {
+                continue;
+            }
+            let id = groups.len() as u32;
+            let hex = tiles[y][x];
+            ids[y * width + x] = id;
+            let mut hexes = Vec::new();
+            let mut queue = VecDeque::from([(x, y)]);
+            while let Some(index) = queue.pop_front() {
+                hexes.push(index);
+                for (n_x, n_y) in grid::neighbours(index, (width, height)) {
+                    if !wraps && n_x.abs_diff(index.0) > 1 {
+                        continue;
+                    }
+                    if ids[n_y * width + n_x] == u32::MAX && together(&hex, &tiles[n_y][n_x]) {
+                        ids[n_y * width + n_x] = id;
+                        queue.push_back((n_x, n_y));
+                    }
+                }
+            }
+            groups.push(hexes);
+        }
+    }
+    (ids, groups)
+}
+
+// the hex of the area with the largest distance to any hex outside of it (or the top/bottom edge of
+// the map), the first one of several equally distant hexes
+fn centre(
+    hexes: &[(usize, usize)],
+    ids: &[u32],
+    id: u32,
+    dimensions: (usize, usize),
+) -> (usize, usize) {
+    let width = dimensions.0;
+    let mut distances: HashMap<(usize, usize), u32> = HashMap::new();
+    let mut queue = VecDeque::new();
+    for &index in hexes {
+        let neighbours = grid::neighbours(index, dimensions);
+        if neighbours.len() < 6 || neighbours.iter().any(|&(x, y)| ids[y * width + x] != id) {
+            distances.insert(index, 0);
+            queue.push_back(index);
+        }
+    }
+    while let Some(index) = queue.pop_front() {
+        let distance = distances[&index];
+        for (x, y) in grid::neighbours(index, dimensions) {
+            if ids[y * width + x] == id && !distances.contains_key(&(x, y)) {
+                distances.insert((x, y), distance + 1);
+                queue.push_back((x, y));
+            }
+        }
+    }
+    let mut centre = hexes[0];
+    for &index in hexes {
+        if distances[&index] > distances[&centre] {
+            centre = index;
+        }
+    }
+    centre
+}
+
+// the bounding box of the hexes, which starts after the widest gap between their columns, so that
+// areas across the left/right edge of the map don't span all of it
+fn bounds(hexes: &[(usize, usize)], width: usize) -> ((usize, usize), (usize, usize)) {
+    let min_y = hexes.iter().map(|h| h.1).min().unwrap_or(0);
+    let max_y = hexes.iter().map(|h| h.1).max().unwrap_or(0);
+    let min_x = hexes.iter().map(|h| h.0).min().unwrap_or(0);
+    let max_x = hexes.iter().map(|h| h.0).max().unwrap_or(0);
+    // only areas touching both edges can continue across them
+    if min_x > 0 || max_x + 1 < width {
+        return ((min_x, min_y), (max_x, max_y));
+    }
+
+    let mut occupied = vec![false; width];
+    for &(x, _) in hexes {
+        occupied[x] = true;
+    }
+    // the widest run of empty columns, wrapping around
+    let (mut gap_start, mut gap_length) = (0, 0);
+    let mut x = 0;
+    while x < width {
+        if occupied[x] {
+            x += 1;
+            continue;
+        }
+        let start = x;
+        while x < width && !occupied[x] {
+            x += 1;
+        }
+        if x - start > gap_length {
+            (gap_start, gap_length) = (start, x - start);
+        }
+    }
+    if gap_length == 0 {
+        // every column is occupied
+        return ((0, min_y), (width - 1, max_y));
+    }
+    (
+        ((gap_start + gap_length) % width, min_y),
+        ((gap_start + width - 1) % width, max_y),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // land at both ends of the first row, the rest is water
+    fn tiles() -> Vec<Vec<Hex>> {
+        let hex = |environment| Hex {
+            environment,
+            ..Hex::EMPTY
+        };
+        let mut tiles = vec![vec![hex(Environment::AQUATIC); 4]; 2];
+        tiles[0][0] = hex(Environment::PLAINS);
+        tiles[0][3] = hex(Environment::PLAINS);
+        tiles
+    }
+
+    #[test]
+    fn groups_only_continue_across_the_edge_if_the_map_wraps() {
+        let same = |a: &Hex, b: &Hex| a.environment == b.environment;
+        let (ids, groups) = flood_fill(&tiles(), true, same);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0], vec![(0, 0), (3, 0)]);
+        assert_eq!(ids[3], 0);
+
+        let (ids, groups) = flood_fill(&tiles(), false, same);
+        assert_eq!(groups.len(), 3);
+        assert_eq!((ids[0], ids[1], ids[3]), (0, 1, 2));
+    }
+
+    #[test]
+    fn small_bodies_of_water_are_lakes() {
+        let tiles = tiles();
+        assert!(lakes(&tiles, 6)[1][2]);
+        assert!(!lakes(&tiles, 6)[0][0]);
+        assert!(!lakes(&tiles, 5)[1][2]);
+
+        let areas = find_areas(&tiles, 6);
+        assert_eq!(areas.body_at((1, 1)).map(|a| a.kind), Some(AreaKind::Lake));
+        assert_eq!(
+            areas.body_at((3, 0)).map(|a| a.kind),
+            Some(AreaKind::Landmass)
+        );
+    }
+}
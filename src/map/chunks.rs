@@ -0,0 +1,79 @@
+use crate::map::playback::{Playback, PlaybackGranularity};
+use crate::map::{Hex, Map};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use std::cmp::min;
+use std::ops::Range;
+use std::sync::{Arc, RwLock};
+
+// amount of rows that are generated together by a single worker
+const CHUNK_ROWS: usize = 16;
+
+// splits the rows of a map into the chunks that can be generated independently of each other
+pub fn row_chunks(height: usize) -> Vec<Range<usize>> {
+    (0..height)
+        .step_by(CHUNK_ROWS)
+        .map(|start| start..min(start + CHUNK_ROWS, height))
+        .collect()
+}
+
+// Phases other than populating/smoothing count down from the maximum, so that they never share a
+// stream with the generator's smoothing iterations
+pub const SETTLEMENT_PHASE: u32 = u32::MAX;
+pub const UNDERGROUND_PHASE: u32 = u32::MAX - 1;
+pub const ENTRANCE_PHASE: u32 = u32::MAX - 2;
+pub const ELEVATION_PHASE: u32 = u32::MAX - 3;
+pub const TRENCH_PHASE: u32 = u32::MAX - 4;
+pub const UNDERSEA_PHASE: u32 = u32::MAX - 5;
+pub const TECTONICS_PHASE: u32 = u32::MAX - 6;
+pub const CLIMATE_PHASE: u32 = u32::MAX - 7;
+pub const REGION_PHASE: u32 = u32::MAX - 8;
+pub const NAME_PHASE: u32 = u32::MAX - 9;
+pub const BOUNDARY_PHASE: u32 = u32::MAX - 10;
+
+// Every phase/chunk combination gets its own stream of the seeded RNG, so the generated map
+// doesn't depend on which worker processes which chunk or in which order the chunks finish
+pub fn chunk_rng(seed: u64, phase: u32, chunk: usize) -> ChaCha8Rng {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    rng.set_stream(((phase as u64) << 32) | chunk as u64);
+    rng
+}
+
+// Writes the rows a chunk generated into the shared map. To not lock the map for every single
+// hex, rows are only committed once they are complete unless the playback is supposed to show
+// the individual hexes
+pub struct ChunkWriter<'a> {
+    map: &'a Arc<RwLock<Map>>,
+    playback: &'a Playback,
+}
+
+impl<'a> ChunkWriter<'a> {
+    pub fn new(map: &'a Arc<RwLock<Map>>, playback: &'a Playback) -> ChunkWriter<'a> {
+        ChunkWriter { map, playback }
+    }
+
+    pub fn hex_done(&self, x: usize, y: usize, hex: Hex) {
+        if self.playback.is_visible(PlaybackGranularity::Hex) {
+            match self.map.write() {
+                Ok(mut map) => map.tiles[y][x] = hex,
+                Err(e) => eprintln!("failed to set hex: {e}"),
+            }
+        }
+        self.playback.checkpoint(PlaybackGranularity::Hex);
+    }
+
+    pub fn row_done(&self, y: usize, row: &[Hex]) {
+        match self.map.write() {
+            Ok(mut map) => map.tiles[y].copy_from_slice(row),
+            Err(e) => eprintln!("failed to set row: {e}"),
+        }
+        self.playback.checkpoint(PlaybackGranularity::Row);
+    }
+}
+
+// copy of the whole map, so that phases that modify the map can be parallelized without the
+// result depending on the order in which the hexes are processed
+pub fn snapshot(map: &Arc<RwLock<Map>>) -> Result<Vec<Vec<Hex>>, String> {
+    let map = map.read().map_err(|e| e.to_string())?;
+    Ok(map.tiles.clone())
+}
@@ -0,0 +1,188 @@
+use crate::config::ClimateConfig;
+use crate::map::chunks::{chunk_rng, row_chunks, snapshot, CLIMATE_PHASE};
+use crate::map::environments::Environment;
+use crate::map::{grid, Hex, Map};
+use rand::Rng;
+use rayon::prelude::*;
+use std::sync::{Arc, RwLock};
+
+// environments that only depend on the climate, all others are left as they are
+const CLIMATE_ENVIRONMENTS: [Environment; 4] = [
+    Environment::DESERT,
+    Environment::FOREST,
+    Environment::PLAINS,
+    Environment::SWAMP,
+];
+
+// Simulates the climate of the finished terrain (which requires the elevation to be derived
+// already). The temperature drops towards the poles and with the height. The prevailing winds carry
+// the moisture they pick up over the sea along the rows of the map and lose it as rain over land,
+// most of all when they have to climb, so the lee side of mountains stays dry. Deserts, forests,
+// plains and swamps are then reclassified based on the climate, unless `reclassify` is false
+pub fn simulate(
+    map: &Arc<RwLock<Map>>,
+    dimensions: (u16, u16),
+    seed: u64,
+    config: &ClimateConfig,
+    reclassify: bool,
+) -> Result<(), String> {
+    if !config.enabled {
+        return Ok(());
+    }
+    let tiles = snapshot(map)?;
+    let dimensions = (dimensions.0 as usize, dimensions.1 as usize);
+    let height = dimensions.1;
+    let max_y = height - 1;
+    let is_water: Vec<bool> = Environment::all().iter().map(|e| e.is_water()).collect();
+
+    let temperatures: Vec<Vec<f32>> = (0..height)
+        .into_par_iter()
+        .map(|y| {
+            let sea_level = sea_level_temperature(latitude(y, max_y), config);
+            tiles[y]
+                .iter()
+                .map(|hex| sea_level - hex.elevation.max(0) as f32 * config.lapse_rate / 1000f32)
+                .collect()
+        })
+        .collect();
+    let precipitation: Vec<Vec<f32>> = (0..height)
+        .into_par_iter()
+        .map(|y| {
+            carry_moisture(
+                &tiles[y],
+                &temperatures[y],
+                &is_water,
+                latitude(y, max_y),
+                config,
+            )
+        })
+        .collect();
+    // the winds don't follow the rows exactly, which also blurs the borders between them
+    let precipitation = blur(&blur(&precipitation, dimensions), dimensions);
+
+    let rows: Vec<Vec<Hex>> = row_chunks(height)
+        .into_par_iter()
+        .enumerate()
+        .flat_map_iter(|(chunk, rows)| {
+            let mut rng = chunk_rng(seed, CLIMATE_PHASE, chunk);
+            let mut chunk_rows = Vec::with_capacity(rows.len());
+            for y in rows {
+                let mut row = tiles[y].clone();
+                for (x, hex) in row.iter_mut().enumerate() {
+                    let variation = config.variation as i32;
+                    let factor = 100 + rng.gen_range(-variation..=variation);
+                    // float to integer casts saturate
+                    hex.temperature = temperatures[y][x].round() as i8;
+                    hex.precipitation = (precipitation[y][x] * factor as f32 / 100f32) as u16;
+                    if reclassify && CLIMATE_ENVIRONMENTS.contains(&hex.environment) {
+                        hex.environment = classify(hex, config);
+                    }
+                }
+                chunk_rows.push(row);
+            }
+            chunk_rows
+        })
+        .collect();
+
+    map.write().map_err(|e| e.to_string())?.tiles = rows;
+    Ok(())
+}
+
+// in degrees, positive in the northern half of the map
+pub(super) fn latitude(y: usize, max_y: usize) -> f32 {
+    let equator = max_y as f32 / 2f32;
+    (equator - y as f32) / equator * 90f32
+}
+
+fn sea_level_temperature(latitude: f32, config: &ClimateConfig) -> f32 {
+    let (equator, pole) = (
+        config.equator_temperature as f32,
+        config.pole_temperature as f32,
+    );
+    pole + (equator - pole) * latitude.to_radians().cos()
+}
+
+// Precipitation of every hex of a row. The trade winds (up to 30 degrees) and the polar easterlies
+// (beyond 60 degrees) blow west, the westerlies in between blow east. The wind travels around the
+// map twice, so it already carries moisture when it reaches the hex it started at
+fn carry_moisture(
+    row: &[Hex],
+    temperatures: &[f32],
+    is_water: &[bool],
+    latitude: f32,
+    config: &ClimateConfig,
+) -> Vec<f32> {
+    let width = row.len();
+    let westerlies = (30f32..60f32).contains(&latitude.abs());
+    // air rises (and rains) at the equator and around 60 degrees and sinks around 30 degrees and at
+    // the poles, where it hardly rains
+    let rising = (6f32 * latitude.to_radians()).cos();
+    let rain_factor =
+        1f32 - config.subsidence_drying.min(100) as f32 / 100f32 * (1f32 - rising) / 2f32;
+    let recycling = config.recycling.min(100) as f32 / 100f32;
+
+    let mut precipitation = vec![0f32; width];
+    let mut moisture = 0f32;
+    let mut previous_elevation = 0f32;
+    for step in 0..2 * width {
+        let x = if westerlies {
+            step % width
+        } else {
+            width - 1 - step % width
+        };
+        let hex = &row[x];
+        let elevation = hex.elevation.max(0) as f32;
+        let rain = if is_water[hex.environment.id() as usize] {
+            // warm water evaporates more and warm air can carry more moisture
+            let warmth = ((temperatures[x] + 10f32) / 40f32).clamp(0.1, 1f32);
+            moisture = (moisture + config.evaporation as f32 * warmth)
+                .min(config.max_moisture as f32 * warmth);
+            moisture * config.rainfall as f32 / 100f32 * rain_factor
+        } else {
+            let climb = (elevation - previous_elevation).max(0f32);
+            let share = (config.rainfall as f32
+                + config.orographic_rainfall as f32 * climb / 1000f32)
+                / 100f32;
+            let rain = moisture * share.min(1f32) * rain_factor;
+            moisture -= rain * (1f32 - recycling);
+            rain
+        };
+        previous_elevation = elevation;
+        if step >= width {
+            precipitation[x] = rain;
+        }
+    }
+    precipitation
+}
+
+// every hex takes on the average of itself and its neighbours
+fn blur(values: &[Vec<f32>], dimensions: (usize, usize)) -> Vec<Vec<f32>> {
+    (0..dimensions.1)
+        .into_par_iter()
+        .map(|y| {
+            (0..dimensions.0)
+                .map(|x| {
+                    let neighbours = grid::neighbours((x, y), dimensions);
+                    let total: f32 = neighbours.iter().map(|&(n_x, n_y)| values[n_y][n_x]).sum();
+                    (total + values[y][x]) / (neighbours.len() + 1) as f32
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn classify(hex: &Hex, config: &ClimateConfig) -> Environment {
+    if hex.temperature < config.arctic_temperature {
+        Environment::ARCTIC
+    } else if hex.precipitation < config.desert_precipitation {
+        Environment::DESERT
+    } else if hex.precipitation >= config.swamp_precipitation
+        && hex.elevation <= config.swamp_max_elevation
+    {
+        Environment::SWAMP
+    } else if hex.precipitation >= config.forest_precipitation {
+        Environment::FOREST
+    } else {
+        Environment::PLAINS
+    }
+}
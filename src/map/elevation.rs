@@ -0,0 +1,175 @@
+use crate::config::ElevationConfig;
+use crate::map::chunks::{chunk_rng, row_chunks, snapshot, ELEVATION_PHASE, TRENCH_PHASE};
+use crate::map::environments::Environment;
+use crate::map::{grid, Hex, Map};
+use rand::Rng;
+use rayon::prelude::*;
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+use std::sync::{Arc, RwLock};
+
+// Derives the elevation of every hex from the finished terrain: the further away from the coast,
+// the higher the land and the deeper the sea (which drops off steeply once past the continental
+// shelf). Any elevation the generator already assigned (e.g. tectonic uplift) is added on top.
+// Trenches are carved into the abyssal plain afterwards
+pub fn derive(
+    map: &Arc<RwLock<Map>>,
+    dimensions: (u16, u16),
+    seed: u64,
+    config: &ElevationConfig,
+) -> Result<(), String> {
+    let tiles = snapshot(map)?;
+    let dimensions = (dimensions.0 as usize, dimensions.1 as usize);
+    let (width, height) = dimensions;
+    let environments = Environment::all();
+    let is_water: Vec<bool> = environments.iter().map(|e| e.is_water()).collect();
+    let extra_heights: Vec<i32> = environments
+        .iter()
+        .map(|e| config.environment_heights.get(e).copied().unwrap_or(0) as i32)
+        .collect();
+    let coast_distances = coast_distances(&tiles, &is_water, dimensions);
+
+    let mut elevations: Vec<Vec<i16>> = row_chunks(height)
+        .into_par_iter()
+        .enumerate()
+        .flat_map_iter(|(chunk, rows)| {
+            let mut rng = chunk_rng(seed, ELEVATION_PHASE, chunk);
+            let mut chunk_rows = Vec::with_capacity(rows.len());
+            for y in rows {
+                let mut row = vec![0; width];
+                for (x, elevation) in row.iter_mut().enumerate() {
+                    let environment = tiles[y][x].environment.id() as usize;
+                    let distance = coast_distances[y][x] as i32;
+                    let base = if is_water[environment] {
+                        -sea_depth(distance, config)
+                    } else {
+                        distance * config.land_rise_per_hex as i32 + extra_heights[environment]
+                    };
+                    let variation = config.variation as i32;
+                    let factor = 100 + rng.gen_range(-variation..=variation);
+                    let mut total = base * factor / 100 + tiles[y][x].elevation as i32;
+                    if is_water[environment] {
+                        // uplift may make the sea shallower but never lift it above the surface
+                        total = total.min(-1);
+                    }
+                    *elevation = total.clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+                }
+                chunk_rows.push(row);
+            }
+            chunk_rows
+        })
+        .collect();
+
+    carve_trenches(
+        &mut elevations,
+        &tiles,
+        &is_water,
+        &coast_distances,
+        dimensions,
+        seed,
+        config,
+    );
+
+    let mut map = map.write().map_err(|e| e.to_string())?;
+    for (y, row) in elevations.iter().enumerate() {
+        for (x, elevation) in row.iter().enumerate() {
+            map.tiles[y][x].elevation = *elevation;
+        }
+    }
+    Ok(())
+}
+
+// depth (without variation) of the sea floor at the given distance from the coast
+fn sea_depth(distance: i32, config: &ElevationConfig) -> i32 {
+    let (shelf_depth, shelf_width) = (config.shelf_depth as i32, config.shelf_width.max(1) as i32);
+    let depth = if distance <= shelf_width {
+        shelf_depth * distance / shelf_width
+    } else {
+        shelf_depth + (distance - shelf_width) * config.slope_per_hex as i32
+    };
+    depth.min(config.abyssal_depth as i32)
+}
+
+// Trenches wind through the abyssal plain, deepest in their middle, and end early when running
+// into shallower water
+fn carve_trenches(
+    elevations: &mut [Vec<i16>],
+    tiles: &[Vec<Hex>],
+    is_water: &[bool],
+    coast_distances: &[Vec<u16>],
+    dimensions: (usize, usize),
+    seed: u64,
+    config: &ElevationConfig,
+) {
+    let abyssal = |(x, y): (usize, usize)| {
+        is_water[tiles[y][x].environment.id() as usize]
+            && sea_depth(coast_distances[y][x] as i32, config) >= config.abyssal_depth as i32
+    };
+    let (abyssal_depth, trench_depth) = (config.abyssal_depth as f32, config.trench_depth as f32);
+    let length = config.trench_length.max(1) as f32;
+    let mut rng = chunk_rng(seed, TRENCH_PHASE, 0);
+
+    let (width, height) = dimensions;
+    for y in 0..height {
+        for x in 0..width {
+            if !abyssal((x, y)) || !rng.gen_ratio(config.trench_chance.min(1000), 1000) {
+                continue;
+            }
+            let mut direction = rng.gen_range(0..grid::DIRECTIONS.len() as u8);
+            let mut current = (x, y);
+            for step in 0..config.trench_length {
+                let profile = (PI * (step as f32 + 0.5) / length).sin();
+                let depth = abyssal_depth + (trench_depth - abyssal_depth) * profile;
+                let elevation = &mut elevations[current.1][current.0];
+                *elevation = (*elevation).min(-depth as i16);
+
+                // mostly keeps its direction, occasionally bending a little
+                if rng.gen_ratio(1, 4) {
+                    direction = if rng.gen() {
+                        (direction + 1) % 6
+                    } else {
+                        (direction + 5) % 6
+                    };
+                }
+                match grid::neighbour(current, direction, dimensions) {
+                    Some(next) if abyssal(next) => current = next,
+                    _ => break,
+                }
+            }
+        }
+    }
+}
+
+// distance of every hex to the closest hex on the other side of the coast (1 for the hexes right
+// at the coast), measured on land for land and in the water for water
+fn coast_distances(
+    tiles: &[Vec<Hex>],
+    is_water: &[bool],
+    dimensions: (usize, usize),
+) -> Vec<Vec<u16>> {
+    let (width, height) = dimensions;
+    let water = |(x, y): (usize, usize)| is_water[tiles[y][x].environment.id() as usize];
+    let mut distances = vec![vec![u16::MAX; width]; height];
+    let mut queue = VecDeque::new();
+    for (y, row) in distances.iter_mut().enumerate() {
+        for (x, distance) in row.iter_mut().enumerate() {
+            let coast = grid::neighbours((x, y), dimensions)
+                .into_iter()
+                .any(|n| water(n) != water((x, y)));
+            if coast {
+                *distance = 1;
+                queue.push_back((x, y));
+            }
+        }
+    }
+    while let Some(current) = queue.pop_front() {
+        let distance = distances[current.1][current.0] + 1;
+        for (n_x, n_y) in grid::neighbours(current, dimensions) {
+            if distances[n_y][n_x] == u16::MAX && water((n_x, n_y)) == water(current) {
+                distances[n_y][n_x] = distance;
+                queue.push_back((n_x, n_y));
+            }
+        }
+    }
+    distances
+}
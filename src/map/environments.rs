@@ -1,39 +1,463 @@
+use once_cell::sync::Lazy;
 use sdl2::pixels::Color;
+use std::cell::RefCell;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 
-#[derive(Copy, Clone, Eq, Hash, PartialEq, Debug)]
-pub enum Environment {
-    NONE,
+// Only an id to keep `Hex` compact, everything else about an environment is looked up in the
+// registry. Besides the built-in environments below, further ones can be defined in the config
+// or registered via `register`
+#[derive(Copy, Clone, Eq, Hash, PartialEq, Ord, PartialOrd)]
+pub struct Environment(u8);
+
+impl Environment {
+    pub const NONE: Environment = Environment(0);
     // Common Environments
-    AQUATIC,
-    ARCTIC,
-    DESERT,
-    FOREST,
-    MOUNTAIN,
-    PLAINS,
-    // TODO: Urban ?
-    SWAMP,
+    pub const AQUATIC: Environment = Environment(1);
+    pub const ARCTIC: Environment = Environment(2);
+    pub const DESERT: Environment = Environment(3);
+    pub const FOREST: Environment = Environment(4);
+    pub const MOUNTAIN: Environment = Environment(5);
+    pub const PLAINS: Environment = Environment(6);
+    pub const SWAMP: Environment = Environment(7);
     // Extreme Environments
-    AERIAL,
-    GLACIER,
-    VOLCANIC,
-    // TODO: Undersea?
-    // TODO: Underground?
-}
+    pub const AERIAL: Environment = Environment(8);
+    pub const GLACIER: Environment = Environment(9);
+    pub const VOLCANIC: Environment = Environment(10);
+    // only generated for the hexes of cities, see `settlements::place`
+    pub const URBAN: Environment = Environment(11);
+    // Underground Environments (see `underground::generate`)
+    pub const ROCK: Environment = Environment(12);
+    pub const CAVERN: Environment = Environment(13);
+    pub const FLOODED_CAVE: Environment = Environment(14);
+    // Undersea Environments (see `undersea::classify`), `AQUATIC` remains for lakes
+    pub const SHALLOWS: Environment = Environment(15);
+    pub const OPEN_SEA: Environment = Environment(16);
+    pub const DEEP_OCEAN: Environment = Environment(17);
+    pub const TRENCH: Environment = Environment(18);
+    pub const REEF: Environment = Environment(19);
+    pub const KELP_FOREST: Environment = Environment(20);
+
+    pub fn id(self) -> u8 {
+        self.0
+    }
+
+    pub fn definition(self) -> EnvironmentDefinition {
+        self.lookup(|d| d.clone())
+    }
+
+    // avoids cloning the whole definition for frequently accessed properties
+    fn lookup<T, F: FnOnce(&EnvironmentDefinition) -> T>(self, f: F) -> T {
+        CACHED.with(|cached| {
+            let version = VERSION.load(Ordering::Acquire);
+            if cached.borrow().0 != version {
+                *cached.borrow_mut() = (version, registry());
+            }
+            f(cached.borrow().1.definition(self))
+        })
+    }
+
+    // name used to refer to the environment in config files
+    pub fn key(self) -> String {
+        self.lookup(|d| d.key.clone())
+    }
+
+    pub fn name(self) -> String {
+        self.lookup(|d| d.name.clone())
+    }
+
+    pub fn from_key(key: &str) -> Option<Environment> {
+        registry().find(key)
+    }
 
-impl Environment {
     pub fn color(self) -> Color {
-        match self {
-            Environment::NONE => Color::RGB(40, 40, 40),
-            Environment::AQUATIC => Color::RGB(0, 130, 220),
-            Environment::ARCTIC => Color::RGB(145, 230, 230),
-            Environment::DESERT => Color::RGB(230, 230, 30),
-            Environment::FOREST => Color::RGB(10, 105, 15),
-            Environment::MOUNTAIN => Color::RGB(88, 97, 96),
-            Environment::PLAINS => Color::RGB(0, 205, 12),
-            Environment::SWAMP => Color::RGB(50, 80, 10),
-            Environment::AERIAL => Color::RGB(202, 216, 214),
-            Environment::GLACIER => Color::RGB(216, 255, 255),
-            Environment::VOLCANIC => Color::RGB(154, 5, 3),
+        let [r, g, b] = self.lookup(|d| d.color);
+        Color::RGB(r, g, b)
+    }
+
+    pub fn glyph(self) -> char {
+        self.lookup(|d| d.glyph())
+    }
+
+    pub fn has_trait(self, name: &str) -> bool {
+        self.lookup(|d| d.traits.iter().any(|t| t == name))
+    }
+
+    pub fn movement_cost(self) -> Option<u8> {
+        self.lookup(|d| d.movement_cost)
+    }
+
+    pub fn is_water(self) -> bool {
+        self.has_trait("aquatic")
+    }
+
+    // all registered environments ordered by their id
+    pub fn all() -> Vec<Environment> {
+        registry().definitions.iter().map(|d| d.id).collect()
+    }
+}
+
+impl fmt::Debug for Environment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.key().to_uppercase())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct EnvironmentDefinition {
+    pub id: Environment,
+    // unique name used to refer to the environment in config files
+    pub key: String,
+    // human-readable name
+    pub name: String,
+    pub color: [u8; 3],
+    // PF2e environment/terrain traits (e.g. "difficult terrain")
+    pub traits: Vec<String>,
+    // hexploration travel time multiplier (2 for difficult, 3 for greater difficult terrain),
+    // `None` if the environment cannot be traversed on foot
+    pub movement_cost: Option<u8>,
+    // path to an image representing the environment
+    pub icon: Option<String>,
+    // character representing the environment in text (see `rendering::text`)
+    pub glyph: Option<char>,
+}
+
+impl EnvironmentDefinition {
+    // the first letter of the key if the environment has no glyph of its own
+    pub fn glyph(&self) -> char {
+        self.glyph
+            .or_else(|| self.key.chars().next())
+            .unwrap_or('?')
+    }
+}
+
+pub struct EnvironmentRegistry {
+    // indexed by the id of the environments
+    definitions: Vec<EnvironmentDefinition>,
+}
+
+impl EnvironmentRegistry {
+    pub fn built_in() -> EnvironmentRegistry {
+        EnvironmentRegistry {
+            definitions: built_in_definitions(),
+        }
+    }
+
+    // The built-in environments and the ones added via `register` with the given ones added or
+    // replacing the ones with the same key. The environments of the current registry keep their
+    // order, so that their ids only change if one before them is left out. The provided ids are
+    // ignored
+    pub fn with_definitions(
+        definitions: Vec<EnvironmentDefinition>,
+    ) -> Result<EnvironmentRegistry, String> {
+        let current = registry();
+        let registered = match REGISTERED.lock() {
+            Ok(registered) => registered.clone(),
+            Err(e) => e.into_inner().clone(),
+        };
+        let mut result = built_in_definitions();
+        let mut added: Vec<EnvironmentDefinition> = Vec::new();
+        for definition in registered.into_iter().chain(definitions) {
+            if let Some(idx) = result.iter().position(|d| d.key == definition.key) {
+                result[idx] = EnvironmentDefinition {
+                    id: result[idx].id,
+                    ..definition
+                };
+            } else if let Some(idx) = added.iter().position(|d| d.key == definition.key) {
+                added[idx] = definition;
+            } else {
+                added.push(definition);
+            }
+        }
+        added.sort_by_key(|d| current.find(&d.key).map_or(usize::MAX, |e| e.0 as usize));
+        for definition in added {
+            let idx = u8::try_from(result.len())
+                .map_err(|_| format!("Cannot register more than {} environments", u8::MAX))?;
+            result.push(EnvironmentDefinition {
+                id: Environment(idx),
+                ..definition
+            });
         }
+        Ok(EnvironmentRegistry {
+            definitions: result,
+        })
     }
+
+    // ordered by their id
+    pub fn definitions(&self) -> &[EnvironmentDefinition] {
+        &self.definitions
+    }
+
+    pub fn find(&self, key: &str) -> Option<Environment> {
+        self.definitions.iter().find(|d| d.key == key).map(|d| d.id)
+    }
+
+    // the definition of `NONE` for unknown environments
+    pub fn definition(&self, environment: Environment) -> &EnvironmentDefinition {
+        self.definitions
+            .get(environment.0 as usize)
+            .unwrap_or(&self.definitions[0])
+    }
+}
+
+static REGISTRY: Lazy<RwLock<Arc<EnvironmentRegistry>>> =
+    Lazy::new(|| RwLock::new(Arc::new(EnvironmentRegistry::built_in())));
+// definitions added via `register`, which are kept when the registry is replaced (see
+// `with_definitions`)
+static REGISTERED: Mutex<Vec<EnvironmentDefinition>> = Mutex::new(Vec::new());
+// increased whenever the registry is replaced
+static VERSION: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    // the registry as of `VERSION`, so that looking up the properties of environments (e.g. for
+    // every hex of every frame) neither locks the registry nor clones it
+    static CACHED: RefCell<(u64, Arc<EnvironmentRegistry>)> =
+        RefCell::new((VERSION.load(Ordering::Acquire), registry()));
+}
+
+pub fn registry() -> Arc<EnvironmentRegistry> {
+    match REGISTRY.read() {
+        Ok(registry) => registry.clone(),
+        Err(e) => e.into_inner().clone(),
+    }
+}
+
+// Adds a new environment or replaces the definition of an existing one with the same key. Once
+// registered, an environment keeps its id (even when it's redefined), since the id is all a `Hex`
+// knows about its environment. Registered environments remain available when a config is loaded,
+// which may still redefine them. The provided `id` is ignored
+pub fn register(definition: EnvironmentDefinition) -> Result<Environment, String> {
+    let mut registry = REGISTRY.write().map_err(|e| e.to_string())?;
+    let mut registered = REGISTERED.lock().map_err(|e| e.to_string())?;
+    let mut definitions = registry.definitions.clone();
+
+    let id = match definitions.iter().position(|d| d.key == definition.key) {
+        Some(idx) => Environment(idx as u8),
+        None => {
+            let idx = u8::try_from(definitions.len())
+                .map_err(|_| format!("Cannot register more than {} environments", u8::MAX))?;
+            definitions.push(definition.clone());
+            Environment(idx)
+        }
+    };
+    definitions[id.0 as usize] = EnvironmentDefinition {
+        id,
+        ..definition.clone()
+    };
+    registered.retain(|d| d.key != definition.key);
+    registered.push(definition);
+
+    *registry = Arc::new(EnvironmentRegistry { definitions });
+    VERSION.fetch_add(1, Ordering::AcqRel);
+    Ok(id)
+}
+
+// replaces all registered environments at once, e.g. with the ones of a newly loaded config
+pub fn install(new: EnvironmentRegistry) -> Result<(), String> {
+    let mut registry = REGISTRY.write().map_err(|e| e.to_string())?;
+    *registry = Arc::new(new);
+    VERSION.fetch_add(1, Ordering::AcqRel);
+    Ok(())
+}
+
+fn built_in_definitions() -> Vec<EnvironmentDefinition> {
+    let definition =
+        |id: Environment, key: &str, color: [u8; 3], traits: &[&str], movement_cost: Option<u8>| {
+            EnvironmentDefinition {
+                id,
+                key: key.to_string(),
+                name: key[..1].to_uppercase() + &key[1..],
+                color,
+                traits: traits.iter().map(|t| t.to_string()).collect(),
+                movement_cost,
+                icon: None,
+                glyph: built_in_glyph(key),
+            }
+        };
+
+    vec![
+        definition(Environment::NONE, "none", [40, 40, 40], &[], None),
+        definition(
+            Environment::AQUATIC,
+            "aquatic",
+            [0, 130, 220],
+            &["aquatic"],
+            None,
+        ),
+        definition(
+            Environment::ARCTIC,
+            "arctic",
+            [145, 230, 230],
+            &["arctic", "difficult terrain"],
+            Some(2),
+        ),
+        definition(
+            Environment::DESERT,
+            "desert",
+            [230, 230, 30],
+            &["desert"],
+            Some(1),
+        ),
+        definition(
+            Environment::FOREST,
+            "forest",
+            [10, 105, 15],
+            &["forest", "difficult terrain"],
+            Some(2),
+        ),
+        definition(
+            Environment::MOUNTAIN,
+            "mountain",
+            [88, 97, 96],
+            &["mountain", "greater difficult terrain"],
+            Some(3),
+        ),
+        definition(
+            Environment::PLAINS,
+            "plains",
+            [0, 205, 12],
+            &["plains"],
+            Some(1),
+        ),
+        definition(
+            Environment::SWAMP,
+            "swamp",
+            [50, 80, 10],
+            &["swamp", "difficult terrain"],
+            Some(2),
+        ),
+        definition(
+            Environment::AERIAL,
+            "aerial",
+            [202, 216, 214],
+            &["aerial"],
+            None,
+        ),
+        definition(
+            Environment::GLACIER,
+            "glacier",
+            [216, 255, 255],
+            &["arctic", "greater difficult terrain"],
+            Some(3),
+        ),
+        definition(
+            Environment::VOLCANIC,
+            "volcanic",
+            [154, 5, 3],
+            &["volcanic", "hazardous terrain", "difficult terrain"],
+            Some(2),
+        ),
+        definition(
+            Environment::URBAN,
+            "urban",
+            [120, 85, 60],
+            &["urban"],
+            Some(1),
+        ),
+        definition(
+            Environment::ROCK,
+            "rock",
+            [35, 30, 28],
+            &["underground"],
+            None,
+        ),
+        definition(
+            Environment::CAVERN,
+            "cavern",
+            [120, 100, 75],
+            &["underground", "difficult terrain"],
+            Some(2),
+        ),
+        EnvironmentDefinition {
+            name: String::from("Flooded cave"),
+            ..definition(
+                Environment::FLOODED_CAVE,
+                "flooded_cave",
+                [40, 75, 115],
+                &["underground", "aquatic"],
+                None,
+            )
+        },
+        definition(
+            Environment::SHALLOWS,
+            "shallows",
+            [70, 170, 235],
+            &["aquatic"],
+            None,
+        ),
+        EnvironmentDefinition {
+            name: String::from("Open sea"),
+            ..definition(
+                Environment::OPEN_SEA,
+                "open_sea",
+                [20, 110, 200],
+                &["aquatic"],
+                None,
+            )
+        },
+        EnvironmentDefinition {
+            name: String::from("Deep ocean"),
+            ..definition(
+                Environment::DEEP_OCEAN,
+                "deep_ocean",
+                [10, 65, 150],
+                &["aquatic"],
+                None,
+            )
+        },
+        definition(
+            Environment::TRENCH,
+            "trench",
+            [5, 30, 90],
+            &["aquatic"],
+            None,
+        ),
+        definition(
+            Environment::REEF,
+            "reef",
+            [60, 200, 200],
+            &["aquatic", "difficult terrain"],
+            None,
+        ),
+        EnvironmentDefinition {
+            name: String::from("Kelp forest"),
+            ..definition(
+                Environment::KELP_FOREST,
+                "kelp_forest",
+                [30, 125, 120],
+                &["aquatic", "difficult terrain"],
+                None,
+            )
+        },
+    ]
+}
+
+// every environment has a glyph of its own, so that they can be told apart in text
+fn built_in_glyph(key: &str) -> Option<char> {
+    let glyph = match key {
+        "none" => '.',
+        "aquatic" => '~',
+        "arctic" => '*',
+        "desert" => ':',
+        "forest" => 'T',
+        "mountain" => '^',
+        "plains" => '"',
+        "swamp" => '%',
+        "aerial" => '&',
+        "glacier" => '#',
+        "volcanic" => '!',
+        "urban" => '@',
+        "rock" => 'x',
+        "cavern" => 'o',
+        "flooded_cave" => 'w',
+        "shallows" => '-',
+        "open_sea" => '=',
+        "deep_ocean" => 'W',
+        "trench" => '_',
+        "reef" => '+',
+        "kelp_forest" => '$',
+        _ => return None,
+    };
+    Some(glyph)
 }
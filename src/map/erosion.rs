@@ -0,0 +1,300 @@
+use crate::config::ErosionConfig;
+use crate::map::chunks::snapshot;
+use crate::map::environments::Environment;
+use crate::map::{areas, grid, Map};
+use rayon::prelude::*;
+use std::sync::{Arc, RwLock};
+
+// direction (see `grid::DIRECTIONS`) every hex drains into, `None` for the sea
+type Receivers = Vec<Vec<Option<u8>>>;
+
+// Erodes the land (which requires the elevation and climate to be simulated already). In every
+// iteration the rain runs off downhill towards the sea (see `drainage`), picking up sediment where
+// it flows fast (carving valleys) and dropping it where it slows down (filling plains, depressions
+// and lakes), and some of what reaches the sea builds up deltas at the river mouths. Afterwards
+// steep slopes slump until they are no steeper than the talus slope. Hexes with enough water flowing
+// through them carry rivers, which are stored on the edges they cross (like roads). Unless `silt` is
+// false, water that is filled up with sediment turns into swamp
+pub fn erode(
+    map: &Arc<RwLock<Map>>,
+    dimensions: (u16, u16),
+    iterations: u16,
+    config: &ErosionConfig,
+    // see `UnderseaConfig::max_lake_size`
+    max_lake_size: u32,
+    silt: bool,
+) -> Result<(), String> {
+    let mut tiles = snapshot(map)?;
+    let dimensions = (dimensions.0 as usize, dimensions.1 as usize);
+    let (width, height) = dimensions;
+    let is_water: Vec<bool> = Environment::all().iter().map(|e| e.is_water()).collect();
+
+    let mut water: Vec<Vec<bool>> = tiles
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|hex| is_water[hex.environment.id() as usize])
+                .collect()
+        })
+        .collect();
+    // rivers flow through lakes, only the sea drains the land
+    let lakes = areas::lakes(&tiles, max_lake_size);
+    let mut sea: Vec<Vec<bool>> = (0..height)
+        .map(|y| (0..width).map(|x| water[y][x] && !lakes[y][x]).collect())
+        .collect();
+    let mut elevations: Vec<Vec<f32>> = tiles
+        .iter()
+        .map(|row| row.iter().map(|hex| hex.elevation as f32).collect())
+        .collect();
+    // in metres of rain per year
+    let rain: Vec<Vec<f32>> = tiles
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|hex| hex.precipitation as f32 / 1000f32)
+                .collect()
+        })
+        .collect();
+
+    let mut silted = 0;
+    for _ in 0..iterations {
+        let (receivers, order) = drainage(&elevations, &sea, dimensions);
+        let flow = accumulate_flow(&order, &receivers, &rain, dimensions);
+
+        // the sediment each hex receives from upstream, in metres
+        let mut sediment = vec![vec![0f32; width]; height];
+        for &(x, y) in order.iter().rev() {
+            let carried = sediment[y][x];
+            let (r_x, r_y) =
+                match receivers[y][x].and_then(|d| grid::neighbour((x, y), d, dimensions)) {
+                    Some(r) => r,
+                    None => continue,
+                };
+            // rivers cannot carve deeper than the sea level, in depressions the slope is negative
+            let base = if sea[r_y][r_x] {
+                elevations[r_y][r_x].max(0f32)
+            } else {
+                elevations[r_y][r_x]
+            };
+            let slope = elevations[y][x] - base;
+            // lakes don't erode their bed, the sediment settles in them instead
+            let capacity = if water[y][x] {
+                0f32
+            } else {
+                config.sediment_capacity * flow[y][x].sqrt() * slope.max(0f32)
+            };
+            let carried = if carried > capacity {
+                let deposit =
+                    (carried - capacity) * config.deposition_rate.min(100) as f32 / 100f32;
+                elevations[y][x] += deposit;
+                carried - deposit
+            } else {
+                // never digs below the hex downstream or the sea level
+                let limit = (slope / 2f32)
+                    .min(elevations[y][x] - 1f32)
+                    .min(config.max_erosion as f32)
+                    .max(0f32);
+                let eroded = ((capacity - carried) * config.erosion_rate.min(100) as f32 / 100f32)
+                    .min(limit);
+                elevations[y][x] -= eroded;
+                carried + eroded
+            };
+
+            if sea[r_y][r_x] {
+                // the currents wash most of the sediment out into the sea, only rivers carry enough
+                // of it to build up deltas
+                if flow[y][x] >= config.river_flow as f32 {
+                    elevations[r_y][r_x] += carried * config.delta_rate.min(100) as f32 / 100f32;
+                }
+            } else {
+                sediment[r_y][r_x] += carried;
+            }
+        }
+
+        // water that has been filled up with sediment leaves marshland behind (deltas), otherwise
+        // it stays just below the sea level
+        for y in 0..height {
+            for x in 0..width {
+                if !water[y][x] || elevations[y][x] < 0f32 {
+                    continue;
+                }
+                if !silt {
+                    elevations[y][x] = -1f32;
+                    continue;
+                }
+                elevations[y][x] = 1f32;
+                water[y][x] = false;
+                sea[y][x] = false;
+                tiles[y][x].environment = Environment::SWAMP;
+                silted += 1;
+            }
+        }
+
+        elevations = slump(&elevations, &water, dimensions, config);
+    }
+
+    // rivers follow the final slopes, they end at the coast and run through lakes
+    let (receivers, order) = drainage(&elevations, &sea, dimensions);
+    let flow = accumulate_flow(&order, &receivers, &rain, dimensions);
+    let mut rivers = vec![vec![0u8; width]; height];
+    for &(x, y) in &order {
+        let direction = match receivers[y][x] {
+            Some(d) if flow[y][x] >= config.river_flow as f32 => d,
+            _ => continue,
+        };
+        let (r_x, r_y) = match grid::neighbour((x, y), direction, dimensions) {
+            Some(r) => r,
+            None => continue,
+        };
+        if !water[y][x] {
+            rivers[y][x] |= 1 << direction;
+        }
+        if !water[r_y][r_x] {
+            rivers[r_y][r_x] |= 1 << grid::opposite(direction);
+        }
+    }
+
+    let mut river_hexes = 0;
+    for (y, row) in tiles.iter_mut().enumerate() {
+        for (x, hex) in row.iter_mut().enumerate() {
+            let elevation = elevations[y][x].round();
+            // erosion never moves the coast (except for deltas)
+            let elevation = if water[y][x] {
+                elevation.min(-1f32)
+            } else {
+                elevation.max(1f32)
+            };
+            hex.elevation = elevation as i16;
+            hex.rivers = rivers[y][x];
+            if hex.rivers != 0 {
+                river_hexes += 1;
+            }
+        }
+    }
+    map.write().map_err(|e| e.to_string())?.tiles = tiles;
+    eprintln!(
+        "Eroded the terrain, {river_hexes} hexes carry rivers and {silted} hexes of water silted up"
+    );
+    Ok(())
+}
+
+// Priority flood from the sea: returns the direction every other hex drains into and all of these
+// hexes ordered downstream first. Water flows into the lowest neighbour that leads to the sea, so it
+// fills up depressions and lakes until it spills over instead of getting stuck in them
+fn drainage(
+    elevations: &[Vec<f32>],
+    sea: &[Vec<bool>],
+    dimensions: (usize, usize),
+) -> (Receivers, Vec<(usize, usize)>) {
+    let (width, height) = dimensions;
+    // hexes are queued by their (filled up) elevation in whole metres
+    let lowest = elevations.iter().flatten().fold(0f32, |a, &b| a.min(b)) as i32;
+    let highest = elevations.iter().flatten().fold(0f32, |a, &b| a.max(b)) as i32;
+    let mut levels: Vec<Vec<(usize, usize)>> = vec![Vec::new(); (highest - lowest + 1) as usize];
+    let level = |elevation: f32| (elevation as i32 - lowest) as usize;
+
+    let mut receivers = vec![vec![None; width]; height];
+    let mut visited = sea.to_vec();
+    for (y, row) in sea.iter().enumerate() {
+        for (x, &is_sea) in row.iter().enumerate() {
+            // only the sea at the coast can be reached from the land
+            let coast = || {
+                grid::neighbours((x, y), dimensions)
+                    .into_iter()
+                    .any(|(n_x, n_y)| !sea[n_y][n_x])
+            };
+            if is_sea && coast() {
+                levels[level(elevations[y][x])].push((x, y));
+            }
+        }
+    }
+    if levels.iter().all(|l| l.is_empty()) {
+        // without any sea the land drains into its lowest hex
+        let (x, y) = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .min_by(|&(a_x, a_y), &(b_x, b_y)| {
+                elevations[a_y][a_x].total_cmp(&elevations[b_y][b_x])
+            })
+            .unwrap_or((0, 0));
+        visited[y][x] = true;
+        levels[level(elevations[y][x])].push((x, y));
+    }
+
+    let mut order = Vec::new();
+    for current_level in 0..levels.len() {
+        while let Some(current) = levels[current_level].pop() {
+            if !sea[current.1][current.0] {
+                order.push(current);
+            }
+            for direction in grid::DIRECTIONS {
+                let (n_x, n_y) = match grid::neighbour(current, direction, dimensions) {
+                    Some(n) if !visited[n.1][n.0] => n,
+                    _ => continue,
+                };
+                visited[n_y][n_x] = true;
+                receivers[n_y][n_x] = Some(grid::opposite(direction));
+                levels[level(elevations[n_y][n_x]).max(current_level)].push((n_x, n_y));
+            }
+        }
+    }
+    (receivers, order)
+}
+
+// amount of rain that flows through every hex (except for the sea), including the rain falling onto it
+fn accumulate_flow(
+    order: &[(usize, usize)],
+    receivers: &[Vec<Option<u8>>],
+    rain: &[Vec<f32>],
+    dimensions: (usize, usize),
+) -> Vec<Vec<f32>> {
+    let mut flow = vec![vec![0f32; dimensions.0]; dimensions.1];
+    for &(x, y) in order.iter().rev() {
+        flow[y][x] += rain[y][x];
+        if let Some((r_x, r_y)) =
+            receivers[y][x].and_then(|d| grid::neighbour((x, y), d, dimensions))
+        {
+            flow[r_y][r_x] += flow[y][x];
+        }
+    }
+    flow
+}
+
+// material on slopes steeper than the talus slope slides down to the neighbours below it
+fn slump(
+    elevations: &[Vec<f32>],
+    water: &[Vec<bool>],
+    dimensions: (usize, usize),
+    config: &ErosionConfig,
+) -> Vec<Vec<f32>> {
+    let talus = config.talus as f32;
+    // each of the (up to) six neighbours may take its share of the excess
+    let rate = config.thermal_rate.min(100) as f32 / 100f32 / 6f32;
+    (0..dimensions.1)
+        .into_par_iter()
+        .map(|y| {
+            (0..dimensions.0)
+                .map(|x| {
+                    let elevation = elevations[y][x];
+                    if water[y][x] {
+                        return elevation;
+                    }
+                    let change: f32 = grid::neighbours((x, y), dimensions)
+                        .into_iter()
+                        .filter(|&(n_x, n_y)| !water[n_y][n_x])
+                        .map(|(n_x, n_y)| {
+                            let difference = elevations[n_y][n_x] - elevation;
+                            if difference > talus {
+                                (difference - talus) * rate
+                            } else if difference < -talus {
+                                (difference + talus) * rate
+                            } else {
+                                0f32
+                            }
+                        })
+                        .sum();
+                    elevation + change
+                })
+                .collect()
+        })
+        .collect()
+}
@@ -0,0 +1,137 @@
+// Helpers for the offset-row layout used by `HexRenderer`: every odd row is shifted to the right
+// by half a hex. The map wraps around horizontally (which is why its dimensions must be even), but
+// not vertically since the top and bottom rows are the poles
+
+use std::collections::{HashSet, VecDeque};
+
+// The edges of a hex, clockwise starting with the top-left one. Used as bit positions for
+// per-edge data such as roads
+pub const TOP_LEFT: u8 = 0;
+pub const TOP_RIGHT: u8 = 1;
+pub const RIGHT: u8 = 2;
+pub const BOTTOM_RIGHT: u8 = 3;
+pub const BOTTOM_LEFT: u8 = 4;
+pub const LEFT: u8 = 5;
+pub const DIRECTIONS: [u8; 6] = [TOP_LEFT, TOP_RIGHT, RIGHT, BOTTOM_RIGHT, BOTTOM_LEFT, LEFT];
+
+// the direction pointing back across the same edge
+pub fn opposite(direction: u8) -> u8 {
+    (direction + 3) % 6
+}
+
+// the hex sharing the given edge, `None` beyond the top/bottom of the map
+pub fn neighbour(
+    index: (usize, usize),
+    direction: u8,
+    dimensions: (usize, usize),
+) -> Option<(usize, usize)> {
+    let (x, y) = index;
+    let (width, height) = dimensions;
+    let left = if x > 0 { x - 1 } else { width - 1 };
+    let right = if x + 1 < width { x + 1 } else { 0 };
+    let (diagonal_left, diagonal_right) = if y % 2 == 0 { (left, x) } else { (x, right) };
+
+    match direction {
+        TOP_LEFT if y > 0 => Some((diagonal_left, y - 1)),
+        TOP_RIGHT if y > 0 => Some((diagonal_right, y - 1)),
+        RIGHT => Some((right, y)),
+        BOTTOM_RIGHT if y + 1 < height => Some((diagonal_right, y + 1)),
+        BOTTOM_LEFT if y + 1 < height => Some((diagonal_left, y + 1)),
+        LEFT => Some((left, y)),
+        _ => None,
+    }
+}
+
+// amount of steps between neighbours it takes to get from one hex to the other (taking the
+// horizontal wrap-around into account)
+pub fn distance(from: (usize, usize), to: (usize, usize), dimensions: (usize, usize)) -> usize {
+    // axial coordinates of the offset rows
+    let axial = |(x, y): (usize, usize)| (x as i64 - (y as i64 - (y as i64 & 1)) / 2, y as i64);
+    let (q1, r1) = axial(from);
+    let width = dimensions.0 as i64;
+    [-width, 0, width]
+        .iter()
+        .map(|shift| {
+            let (q2, r2) = axial(to);
+            let (dq, dr) = (q2 + shift - q1, r2 - r1);
+            ((dq.abs() + dr.abs() + (dq + dr).abs()) / 2) as usize
+        })
+        .min()
+        .unwrap_or(0)
+}
+
+// the (up to) six hexes sharing an edge with the hex at the given index
+pub fn neighbours(index: (usize, usize), dimensions: (usize, usize)) -> Vec<(usize, usize)> {
+    let (x, y) = index;
+    let (width, height) = dimensions;
+    let left = if x > 0 { x - 1 } else { width - 1 };
+    let right = if x + 1 < width { x + 1 } else { 0 };
+    // the two hexes above/below are either (left, x) or (x, right) depending on the row's shift
+    let (diagonal_left, diagonal_right) = if y % 2 == 0 { (left, x) } else { (x, right) };
+
+    let mut neighbours = Vec::with_capacity(6);
+    if y > 0 {
+        neighbours.push((diagonal_left, y - 1));
+        neighbours.push((diagonal_right, y - 1));
+    }
+    neighbours.push((left, y));
+    neighbours.push((right, y));
+    if y + 1 < height {
+        neighbours.push((diagonal_left, y + 1));
+        neighbours.push((diagonal_right, y + 1));
+    }
+    neighbours
+}
+
+// all hexes within the given distance (in steps between neighbours) of the hex at the given index,
+// including the hex itself, together with their distance
+pub fn within_distance(
+    index: (usize, usize),
+    distance: u16,
+    dimensions: (usize, usize),
+) -> Vec<((usize, usize), u16)> {
+    let mut found = vec![(index, 0)];
+    let mut visited = HashSet::from([index]);
+    let mut queue = VecDeque::from([(index, 0)]);
+    while let Some((current, d)) = queue.pop_front() {
+        if d == distance {
+            continue;
+        }
+        for neighbour in neighbours(current, dimensions) {
+            if visited.insert(neighbour) {
+                found.push((neighbour, d + 1));
+                queue.push_back((neighbour, d + 1));
+            }
+        }
+    }
+    found
+}
+
+// vertical distance between the middle points of two rows, in hex widths
+pub const ROW_HEIGHT: f32 = 0.866_025_4;
+// distance from the middle point to the top/bottom corner, in hex widths
+pub const CORNER_RADIUS: f32 = 0.577_350_3;
+
+// position of the hex's middle point relative to the middle point of the hex (0, 0), in hex widths
+pub fn center(index: (usize, usize)) -> (f32, f32) {
+    let (x, y) = index;
+    let row_offset = (y % 2) as f32 / 2f32;
+    (x as f32 + row_offset, y as f32 * ROW_HEIGHT)
+}
+
+// the hex whose middle point is closest to the position (see `center`), which may lie beyond the
+// edges of the map
+pub fn hex_at(position: (f32, f32)) -> (i64, i64) {
+    let (x, y) = position;
+    let row = (y / ROW_HEIGHT).round() as i64;
+    // the closest hex is in the closest row or (near the corners) in one of the rows around it
+    (row - 1..=row + 1)
+        .map(|r| {
+            let row_offset = r.rem_euclid(2) as f32 / 2f32;
+            let column = (x - row_offset).round() as i64;
+            let (d_x, d_y) = (x - column as f32 - row_offset, y - r as f32 * ROW_HEIGHT);
+            ((column, r), d_x * d_x + d_y * d_y)
+        })
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map_or((0, row), |(index, _)| index)
+}
@@ -0,0 +1,92 @@
+use crate::config::{GeneratorConfig, HeightmapConfig};
+use crate::map::chunks::{row_chunks, ChunkWriter};
+use crate::map::environments::Environment;
+use crate::map::playback::Playback;
+use crate::map::{Hex, Map, MapGenerator};
+use image::{ImageBuffer, Luma};
+use rayon::prelude::*;
+use std::sync::{Arc, RwLock};
+
+// Imports the elevation from a greyscale image (see `HeightmapConfig`), interpolated between the
+// pixels around every hex. Hexes below the sea level become water, the land
+// becomes plains or mountains
+pub struct HeightmapGenerator {
+    // see `load_heightmap`
+    pub heightmap: Heightmap,
+    pub config: Arc<GeneratorConfig>,
+}
+
+pub type Heightmap = ImageBuffer<Luma<u16>, Vec<u16>>;
+
+// the heightmap to import (see `HeightmapConfig`), loaded before the generation starts so that a
+// missing heightmap doesn't lead to an empty map
+pub fn load_heightmap(config: &HeightmapConfig) -> Result<Heightmap, String> {
+    let image = image::open(&config.path)
+        .map_err(|e| format!("failed to import the heightmap '{}': {e}", config.path))?
+        .to_luma16();
+    if image.width() == 0 || image.height() == 0 {
+        return Err(format!("the heightmap '{}' is empty", config.path));
+    }
+    Ok(image)
+}
+
+impl MapGenerator for HeightmapGenerator {
+    fn populate(
+        &self,
+        map: Arc<RwLock<Map>>,
+        dimensions: (u16, u16),
+        _seed: u64,
+        playback: &Playback,
+    ) {
+        let config = &self.config.heightmap;
+        let image = &self.heightmap;
+        let (width, height) = (dimensions.0 as usize, dimensions.1 as usize);
+        let (image_width, image_height) = (image.width() as usize, image.height() as usize);
+        let pixel = |x: usize, y: usize| image.get_pixel(x as u32, y as u32).0[0] as f32;
+        let writer = ChunkWriter::new(&map, playback);
+
+        row_chunks(height).into_par_iter().for_each(|rows| {
+            for y in rows {
+                let mut row = vec![Hex::EMPTY; width];
+                for (x, hex) in row.iter_mut().enumerate() {
+                    // the position of the hex in pixels, ignoring the shift of every second row so
+                    // that exported heightmaps are imported exactly as they were
+                    let p_x = (x as f32 + 0.5) / width as f32 * image_width as f32 - 0.5;
+                    let p_y = (y as f32 + 0.5) / height as f32 * image_height as f32 - 0.5;
+                    let p_y = p_y.clamp(0f32, (image_height - 1) as f32);
+                    // the map wraps around horizontally
+                    let left = p_x.floor().rem_euclid(image_width as f32) as usize;
+                    let right = (left + 1) % image_width;
+                    let top = p_y.floor() as usize;
+                    let bottom = (top + 1).min(image_height - 1);
+                    let (f_x, f_y) = (p_x - p_x.floor(), p_y - p_y.floor());
+                    let value = (pixel(left, top) * (1f32 - f_x) + pixel(right, top) * f_x)
+                        * (1f32 - f_y)
+                        + (pixel(left, bottom) * (1f32 - f_x) + pixel(right, bottom) * f_x) * f_y;
+
+                    hex.elevation = config.from_grey(value);
+                    hex.environment = if hex.elevation < 0 {
+                        Environment::AQUATIC
+                    } else if hex.elevation > config.mountain_elevation {
+                        Environment::MOUNTAIN
+                    } else {
+                        Environment::PLAINS
+                    };
+                    writer.hex_done(x, y, *hex);
+                }
+                writer.row_done(y, &row);
+            }
+        });
+    }
+
+    fn smooth(
+        &self,
+        _map: Arc<RwLock<Map>>,
+        _dimensions: (u16, u16),
+        _seed: u64,
+        _iteration: u16,
+        _playback: &Playback,
+    ) {
+        // the heightmap is imported as it is
+    }
+}
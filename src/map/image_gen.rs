@@ -0,0 +1,155 @@
+use crate::config::{GeneratorConfig, ImportConfig};
+use crate::map::chunks::{row_chunks, ChunkWriter};
+use crate::map::environments::Environment;
+use crate::map::grid;
+use crate::map::playback::Playback;
+use crate::map::{Hex, Map, MapGenerator};
+use image::RgbImage;
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+// amount of unmatched colours that are listed individually
+const REPORTED_COLOURS: usize = 10;
+
+// Imports the environments from an image (see `ImportConfig`). Every hex takes on the environment
+// most of the pixels it covers are painted with, if the image is smaller than the map the pixel at
+// its middle point is used
+pub struct ImageGenerator {
+    // see `load_image`
+    pub image: RgbImage,
+    pub config: Arc<GeneratorConfig>,
+}
+
+// the image to import (see `ImportConfig`), loaded before the generation starts so that a missing
+// image doesn't lead to an empty map
+pub fn load_image(config: &ImportConfig) -> Result<RgbImage, String> {
+    let image = image::open(&config.path)
+        .map_err(|e| format!("failed to import the image '{}': {e}", config.path))?
+        .to_rgb8();
+    if image.width() == 0 || image.height() == 0 {
+        return Err(format!("the image '{}' is empty", config.path));
+    }
+    Ok(image)
+}
+
+impl MapGenerator for ImageGenerator {
+    fn populate(
+        &self,
+        map: Arc<RwLock<Map>>,
+        dimensions: (u16, u16),
+        _seed: u64,
+        playback: &Playback,
+    ) {
+        let config = &self.config.import;
+        let image = &self.image;
+        let environments = match_colours(image, config);
+        let dimensions = (dimensions.0 as usize, dimensions.1 as usize);
+        let (width, height) = dimensions;
+        let (image_width, image_height) = (image.width() as usize, image.height() as usize);
+        let writer = ChunkWriter::new(&map, playback);
+
+        row_chunks(height).into_par_iter().for_each(|rows| {
+            for y in rows {
+                let mut row = vec![Hex::EMPTY; width];
+                for (x, hex) in row.iter_mut().enumerate() {
+                    // the pixels between the left and right edge/top and bottom of the hex (see
+                    // `grid::center`), the map wraps around horizontally
+                    let (c_x, _) = grid::center((x, y));
+                    let left = c_x / width as f32 * image_width as f32;
+                    let right = (c_x + 1f32) / width as f32 * image_width as f32;
+                    let top = y * image_height / height;
+                    let bottom = ((y + 1) * image_height / height).max(top + 1);
+                    let columns = left as usize..(right as usize).max(left as usize + 1);
+
+                    let mut counts: HashMap<Environment, usize> = HashMap::new();
+                    for p_y in top..bottom {
+                        for p_x in columns.clone() {
+                            let pixel = image.get_pixel((p_x % image_width) as u32, p_y as u32);
+                            *counts.entry(environments[&pixel.0]).or_default() += 1;
+                        }
+                    }
+                    // ties are broken by the order of the environments to stay deterministic
+                    hex.environment = counts
+                        .into_iter()
+                        .max_by_key(|&(env, count)| (count, env))
+                        .map_or(Environment::NONE, |(env, _)| env);
+                    writer.hex_done(x, y, *hex);
+                }
+                writer.row_done(y, &row);
+            }
+        });
+    }
+
+    fn smooth(
+        &self,
+        _map: Arc<RwLock<Map>>,
+        _dimensions: (u16, u16),
+        _seed: u64,
+        _iteration: u16,
+        _playback: &Playback,
+    ) {
+        // the image is imported as it was painted
+    }
+}
+
+// the environment with the closest colour of every colour in the image, colours that are not close
+// to any of them are reported
+fn match_colours(image: &RgbImage, config: &ImportConfig) -> HashMap<[u8; 3], Environment> {
+    let palette: Vec<(Environment, [u8; 3])> = if config.palette.is_empty() {
+        Environment::all()
+            .into_iter()
+            .filter(|&e| e != Environment::NONE && !e.has_trait("underground"))
+            .map(|e| {
+                let color = e.color();
+                (e, [color.r, color.g, color.b])
+            })
+            .collect()
+    } else {
+        let mut palette: Vec<_> = config.palette.iter().map(|(&e, &c)| (e, c)).collect();
+        palette.sort();
+        palette
+    };
+    let distance = |a: [u8; 3], b: [u8; 3]| {
+        let squared: i32 = (0..3).map(|i| (a[i] as i32 - b[i] as i32).pow(2)).sum();
+        (squared as f32).sqrt()
+    };
+
+    let mut environments = HashMap::new();
+    let mut unmatched: HashMap<[u8; 3], usize> = HashMap::new();
+    for pixel in image.pixels() {
+        let colour = pixel.0;
+        if let Some(count) = unmatched.get_mut(&colour) {
+            *count += 1;
+            continue;
+        }
+        if environments.contains_key(&colour) {
+            continue;
+        }
+        let (environment, closest) = palette
+            .iter()
+            .map(|&(e, c)| (e, distance(colour, c)))
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .unwrap_or((Environment::NONE, 0f32));
+        environments.insert(colour, environment);
+        if closest > config.tolerance as f32 {
+            unmatched.insert(colour, 1);
+        }
+    }
+
+    if !unmatched.is_empty() {
+        let mut unmatched: Vec<([u8; 3], usize)> = unmatched.into_iter().collect();
+        unmatched.sort_by_key(|&(colour, count)| (usize::MAX - count, colour));
+        eprintln!(
+            "{} colours of the image don't match any environment, they were imported as the closest one:",
+            unmatched.len()
+        );
+        for &([r, g, b], count) in unmatched.iter().take(REPORTED_COLOURS) {
+            eprintln!(
+                "  #{r:02x}{g:02x}{b:02x} ({count} pixels, imported as {:?})",
+                environments[&[r, g, b]]
+            );
+        }
+    }
+    environments
+}
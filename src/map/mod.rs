@@ -1,38 +1,208 @@
-mod environments;
+pub mod areas;
+mod chunks;
+mod climate;
+mod elevation;
+pub mod environments;
+mod erosion;
+pub mod grid;
+mod heightmap_gen;
+mod image_gen;
+pub mod names;
+pub mod playback;
 mod procedural_gen;
 mod random_gen;
+mod region_gen;
+pub mod roads;
+pub mod settlements;
+mod sketch_gen;
+pub mod statistics;
+mod tectonics;
+mod underground;
+mod undersea;
+mod wfc_gen;
 
+use crate::config::{self, GeneratorConfig};
+use crate::map::areas::Areas;
 use crate::map::environments::Environment;
+use crate::map::heightmap_gen::{Heightmap, HeightmapGenerator};
+use crate::map::image_gen::ImageGenerator;
+use crate::map::names::Label;
+use crate::map::playback::{Playback, PlaybackGranularity};
 use crate::map::procedural_gen::ProceduralGenerator;
+use crate::map::random_gen::RandomGenerator;
+use crate::map::region_gen::{Region, RegionGenerator, RegionRecord};
+use crate::map::settlements::Settlement;
+use crate::map::sketch_gen::SketchGenerator;
+use crate::map::wfc_gen::WfcGenerator;
+use image::RgbImage;
+use rand::Rng;
+use std::collections::HashMap;
+use std::str::FromStr;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, RwLock};
 use std::thread;
 
+// Generators must only use the provided seed as source of randomness (see `chunks::chunk_rng`), so
+// that generating a map with the same seed always results in the same map
 trait MapGenerator {
-    fn populate(&self, map: Arc<RwLock<Map>>, dimensions: (u16, u16));
-    fn smooth(&self, map: Arc<RwLock<Map>>, dimensions: (u16, u16));
+    fn populate(
+        &self,
+        map: Arc<RwLock<Map>>,
+        dimensions: (u16, u16),
+        seed: u64,
+        playback: &Playback,
+    );
+    fn smooth(
+        &self,
+        map: Arc<RwLock<Map>>,
+        dimensions: (u16, u16),
+        seed: u64,
+        iteration: u16,
+        playback: &Playback,
+    );
 }
 
 pub struct MapState {
+    // the surface
     pub map: Arc<RwLock<Map>>,
+    pub underground: Arc<RwLock<Map>>,
+    // the layer that is displayed
+    pub layer: MapLayer,
+    // whether the names of the map are displayed
+    pub show_labels: bool,
+    // the generator used for the next generation
+    pub generator: GeneratorKind,
     pub map_size: (u16, u16),
+    // smoothing iterations of the generators that smooth the map
     pub iterations: u16,
+    // iterations of the erosion, more iterations carve deeper valleys
+    pub erosion_iterations: u16,
+    // see `ProceduralGenerator::smoothing_strength`
+    pub smoothing_strength: u8,
+    pub seed: u64,
+    pub playback: Arc<Playback>,
+    // hexes to generate a region of
+    pub selection: Vec<(usize, usize)>,
+    // the part of the parent map this map is generated from, if it is a region
+    region: Option<Arc<Region>>,
+    // the regions of this map that have been generated, by their selection
+    regions: HashMap<Vec<(usize, usize)>, MapState>,
     generating: Arc<AtomicBool>,
 }
 
+// the settings a map is created with, see `MapState::new`
+#[derive(Copy, Clone, Debug)]
+pub struct MapSettings {
+    pub dimensions: (u16, u16),
+    // see `MapState::iterations`
+    pub iterations: u16,
+    pub erosion_iterations: u16,
+    pub smoothing_strength: u8,
+    // a random seed is used if none is provided
+    pub seed: Option<u64>,
+    // the generator of the first map
+    pub generator: GeneratorKind,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum MapLayer {
+    Surface,
+    Underground,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum GeneratorKind {
+    Procedural,
+    Random,
+    WaveFunctionCollapse,
+    // see `SketchConfig`
+    Sketch,
+    // see `ImportConfig`
+    Image,
+    // see `HeightmapConfig`
+    Heightmap,
+}
+
+impl FromStr for GeneratorKind {
+    type Err = String;
+
+    // the names used on the command line
+    fn from_str(name: &str) -> Result<GeneratorKind, String> {
+        match name {
+            "procedural" => Ok(GeneratorKind::Procedural),
+            "random" => Ok(GeneratorKind::Random),
+            "wfc" => Ok(GeneratorKind::WaveFunctionCollapse),
+            "sketch" => Ok(GeneratorKind::Sketch),
+            "image" => Ok(GeneratorKind::Image),
+            "heightmap" => Ok(GeneratorKind::Heightmap),
+            _ => Err(format!(
+                "unknown generator '{name}' (procedural, random, wfc, sketch, image, heightmap)"
+            )),
+        }
+    }
+}
+
+// the layers that the generator already filled in and that are not generated again
+struct Provided {
+    elevation: bool,
+    climate: bool,
+    // the environments are kept as they are (neither reclassified by the climate, silted up, divided
+    // into undersea zones nor turned urban), e.g. so that they keep satisfying the rules they were
+    // generated by
+    environments: bool,
+}
+
 pub struct Map {
     pub tiles: Vec<Vec<Hex>>,
+    pub settlements: Vec<Settlement>,
+    // hexes connected to the same hex of the other layer
+    pub entrances: Vec<(usize, usize)>,
+    // detected once the map is finished
+    pub areas: Areas,
+    // the names of the map, ordered by how important they are (see `names::name`)
+    pub labels: Vec<Label>,
 }
 
 #[derive(Copy, Clone, Eq, Hash, PartialEq)]
 pub struct Hex {
     pub environment: Environment,
+    // bit mask of the edges (see `grid::DIRECTIONS`) a road crosses
+    pub roads: u8,
+    // bit mask of the edges a river crosses, rivers flow into the hex with the lowest elevation
+    pub rivers: u8,
+    // in metres relative to the sea level
+    pub elevation: i16,
+    // mean annual temperature in degrees Celsius
+    pub temperature: i8,
+    // mean annual precipitation in millimetres
+    pub precipitation: u16,
 }
 
-impl MapState {
-    //const GENERATOR: RandomGenerator = RandomGenerator {};
-    const GENERATOR: ProceduralGenerator = ProceduralGenerator {};
+impl Hex {
+    pub const EMPTY: Hex = Hex {
+        environment: Environment::NONE,
+        roads: 0,
+        rivers: 0,
+        elevation: 0,
+        temperature: 0,
+        precipitation: 0,
+    };
+}
+
+// Resets the `generating` flag once dropped, so that a panicking generator doesn't prevent any
+// further map from being generated
+struct GeneratingGuard(Arc<AtomicBool>);
 
+impl Drop for GeneratingGuard {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            eprintln!("map generation failed, the map is most likely incomplete");
+        }
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+impl MapState {
     fn create_empty_map(dimensions: (u16, u16)) -> Result<Map, String> {
         let (width, height) = dimensions;
         if height % 2 != 0 || width % 2 != 0 || height < 2 || width < 2 {
@@ -41,55 +211,416 @@ impl MapState {
         }
 
         Ok(Map {
-            tiles: vec![
-                vec![
-                    Hex {
-                        environment: Environment::NONE
-                    };
-                    width as usize
-                ];
-                height as usize
-            ],
+            tiles: vec![vec![Hex::EMPTY; width as usize]; height as usize],
+            settlements: Vec::new(),
+            entrances: Vec::new(),
+            areas: Areas::default(),
+            labels: Vec::new(),
         })
     }
 
-    pub fn new(dimensions: (u16, u16), iterations: u16) -> Result<MapState, String> {
+    pub fn new(settings: MapSettings) -> Result<MapState, String> {
+        let dimensions = settings.dimensions;
+        let mut state = MapState {
+            map: Arc::new(RwLock::new(MapState::create_empty_map(dimensions)?)),
+            underground: Arc::new(RwLock::new(MapState::create_empty_map(dimensions)?)),
+            layer: MapLayer::Surface,
+            show_labels: true,
+            generator: settings.generator,
+            map_size: dimensions,
+            iterations: settings.iterations,
+            erosion_iterations: settings.erosion_iterations,
+            smoothing_strength: settings.smoothing_strength,
+            seed: settings.seed.unwrap_or_else(|| rand::thread_rng().gen()),
+            playback: Arc::new(Playback::new()),
+            selection: Vec::new(),
+            region: None,
+            regions: HashMap::new(),
+            generating: Arc::new(AtomicBool::new(false)),
+        };
+        state.generate_map()?;
+        Ok(state)
+    }
+
+    pub fn is_generating(&self) -> bool {
+        self.generating.load(Ordering::Acquire)
+    }
+
+    pub fn visible_map(&self) -> &Arc<RwLock<Map>> {
+        match self.layer {
+            MapLayer::Surface => &self.map,
+            MapLayer::Underground => &self.underground,
+        }
+    }
+
+    pub fn toggle_layer(&mut self) {
+        self.layer = match self.layer {
+            MapLayer::Surface => MapLayer::Underground,
+            MapLayer::Underground => MapLayer::Surface,
+        };
+        eprintln!("Showing layer: {:?}", self.layer);
+    }
+
+    pub fn toggle_labels(&mut self) {
+        self.show_labels = !self.show_labels;
+        eprintln!("Showing names: {}", self.show_labels);
+    }
+
+    pub fn cycle_generator(&mut self) {
+        self.generator = match self.generator {
+            GeneratorKind::Procedural => GeneratorKind::Random,
+            GeneratorKind::Random => GeneratorKind::WaveFunctionCollapse,
+            GeneratorKind::WaveFunctionCollapse => GeneratorKind::Sketch,
+            GeneratorKind::Sketch => GeneratorKind::Image,
+            GeneratorKind::Image => GeneratorKind::Heightmap,
+            GeneratorKind::Heightmap => GeneratorKind::Procedural,
+        };
+        eprintln!("Using generator: {:?}", self.generator);
+    }
+
+    pub fn toggle_selection(&mut self, index: (usize, usize)) {
+        match self.selection.iter().position(|&i| i == index) {
+            Some(i) => {
+                self.selection.remove(i);
+            }
+            None => self.selection.push(index),
+        }
+    }
+
+    // The map of the selected hexes at a finer scale, the region is generated the first time and
+    // taken out of the map's regions afterwards (see `store_region`)
+    pub fn enter_region(&mut self) -> Result<MapState, String> {
+        if self.is_generating() {
+            return Err(String::from("the map is still being generated"));
+        }
+        let mut selection = self.selection.clone();
+        selection.sort();
+        selection.dedup();
+        if let Some(region) = self.regions.remove(&selection) {
+            self.selection.clear();
+            return Ok(region);
+        }
+
+        let config = config::current();
+        // a region that has been generated before keeps its seed and scale
+        let records = region_gen::load_records(&config.region.path).unwrap_or_else(|e| {
+            eprintln!("{e}");
+            Vec::new()
+        });
+        let saved = records.into_iter().find(|r| {
+            (r.parent_seed, r.parent_size, &r.selection) == (self.seed, self.map_size, &selection)
+        });
+        let record = match &saved {
+            Some(record) => record.clone(),
+            None => {
+                // every region of the map has its own seed
+                let (x, y) = *selection.first().ok_or("no hexes are selected")?;
+                let chunk = y * self.map_size.0 as usize + x;
+                RegionRecord {
+                    parent_seed: self.seed,
+                    parent_size: self.map_size,
+                    selection: selection.clone(),
+                    seed: chunks::chunk_rng(self.seed, chunks::REGION_PHASE, chunk).gen(),
+                    scale: config.region.scale,
+                }
+            }
+        };
+        let tiles = chunks::snapshot(&self.map)?;
+        let region = Region::new(&tiles, &selection, record.scale)?;
+        if saved.is_none() {
+            if let Err(e) = region_gen::save_record(&config.region.path, &record) {
+                eprintln!("{e}");
+            }
+        }
+        let seed = record.seed;
+        let dimensions = region.dimensions;
+        eprintln!(
+            "Generating a region of {} hexes with dimensions {dimensions:?}",
+            region.selection.len()
+        );
+
         let mut state = MapState {
             map: Arc::new(RwLock::new(MapState::create_empty_map(dimensions)?)),
+            underground: Arc::new(RwLock::new(MapState::create_empty_map(dimensions)?)),
+            layer: self.layer,
+            show_labels: self.show_labels,
+            generator: self.generator,
             map_size: dimensions,
-            iterations,
+            iterations: self.iterations,
+            erosion_iterations: self.erosion_iterations,
+            smoothing_strength: self.smoothing_strength,
+            seed,
+            playback: Arc::new(Playback::new()),
+            selection: Vec::new(),
+            region: Some(Arc::new(region)),
+            regions: HashMap::new(),
             generating: Arc::new(AtomicBool::new(false)),
         };
         state.generate_map()?;
+        self.selection.clear();
         Ok(state)
     }
 
+    // keeps a region that has been left, so that entering it again shows the same map
+    pub fn store_region(&mut self, region: MapState) {
+        if let Some(selection) = region.region.as_ref().map(|r| r.selection.clone()) {
+            self.regions.insert(selection, region);
+        }
+    }
+
+    // picks a new random seed for the next generation
+    pub fn reseed(&mut self) {
+        self.seed = rand::thread_rng().gen();
+    }
+
     pub fn generate_map(&mut self) -> Result<(), String> {
-        let generating = self.generating.clone();
-        if generating.swap(true, Ordering::Acquire) {
-            println!("a previous map generation is still in progress");
+        if self.generating.swap(true, Ordering::Acquire) {
+            eprintln!("a previous map generation is still in progress");
             return Ok(());
         }
+        let guard = GeneratingGuard(self.generating.clone());
+        // the config could be reloaded during the generation, so the generation keeps its own copy
+        let config = config::current();
+        // the files the generators import are loaded up front, without them there is nothing to
+        // generate and the previous map is kept
+        let (mut sketch, mut image, mut heightmap) =
+            (Vec::new(), RgbImage::default(), Heightmap::default());
+        match (&self.region, self.generator) {
+            (None, GeneratorKind::Sketch) => sketch = sketch_gen::load_sketch(&config.sketch)?,
+            (None, GeneratorKind::Image) => image = image_gen::load_image(&config.import)?,
+            (None, GeneratorKind::Heightmap) => {
+                heightmap = heightmap_gen::load_heightmap(&config.heightmap)?
+            }
+            _ => {}
+        }
+        // the regions were generated from the previous map
+        self.regions.clear();
+        self.selection.clear();
 
-        let mut map = self.map.write().map_err(|e| e.to_string())?;
-        *map = MapState::create_empty_map(self.map_size)?;
+        for layer in [&self.map, &self.underground] {
+            let mut map = match layer.write() {
+                Ok(m) => m,
+                Err(e) => {
+                    // a previous generation panicked while writing, the map is replaced anyways
+                    layer.clear_poison();
+                    e.into_inner()
+                }
+            };
+            *map = MapState::create_empty_map(self.map_size)?;
+        }
 
         let local_self = self.map.clone();
+        let underground = self.underground.clone();
         let iterations = self.iterations;
+        let erosion_iterations = self.erosion_iterations;
+        let generator = self.generator;
+        let region = self.region.clone();
+        let smoothing_strength = self.smoothing_strength;
         let dimensions = self.map_size;
+        let seed = self.seed;
+        let playback = self.playback.clone();
+        eprintln!("Generating map with seed {seed}");
 
         thread::spawn(move || {
-            MapState::generate(local_self, iterations, dimensions);
-            generating.store(false, Ordering::SeqCst);
+            let _guard = guard;
+            let map = local_self.clone();
+            match (&region, generator) {
+                // regions always follow their parent map
+                (Some(region), _) => MapState::generate(
+                    &RegionGenerator {
+                        region: region.clone(),
+                        smoothing_strength,
+                        config: config.clone(),
+                    },
+                    map,
+                    iterations,
+                    dimensions,
+                    seed,
+                    &playback,
+                ),
+                (None, GeneratorKind::Procedural) => MapState::generate(
+                    &ProceduralGenerator {
+                        smoothing_strength,
+                        config: config.clone(),
+                    },
+                    map,
+                    iterations,
+                    dimensions,
+                    seed,
+                    &playback,
+                ),
+                (None, GeneratorKind::Random) => MapState::generate(
+                    &RandomGenerator {
+                        config: config.clone(),
+                    },
+                    map,
+                    iterations,
+                    dimensions,
+                    seed,
+                    &playback,
+                ),
+                (None, GeneratorKind::Sketch) => MapState::generate(
+                    &SketchGenerator {
+                        sketch,
+                        smoothing_strength,
+                        config: config.clone(),
+                    },
+                    map,
+                    iterations,
+                    dimensions,
+                    seed,
+                    &playback,
+                ),
+                (None, GeneratorKind::Image) => MapState::generate(
+                    &ImageGenerator {
+                        image,
+                        config: config.clone(),
+                    },
+                    map,
+                    iterations,
+                    dimensions,
+                    seed,
+                    &playback,
+                ),
+                (None, GeneratorKind::Heightmap) => MapState::generate(
+                    &HeightmapGenerator {
+                        heightmap,
+                        config: config.clone(),
+                    },
+                    map,
+                    iterations,
+                    dimensions,
+                    seed,
+                    &playback,
+                ),
+                (None, GeneratorKind::WaveFunctionCollapse) => MapState::generate(
+                    &WfcGenerator {
+                        config: config.clone(),
+                    },
+                    map,
+                    iterations,
+                    dimensions,
+                    seed,
+                    &playback,
+                ),
+            }
+            MapState::generate_features(
+                &config,
+                &local_self,
+                &underground,
+                dimensions,
+                seed,
+                erosion_iterations,
+                Provided {
+                    elevation: region.is_none() && generator == GeneratorKind::Heightmap,
+                    // regions take over the climate of their parent map
+                    climate: region.is_some(),
+                    environments: region.is_none()
+                        && matches!(
+                            generator,
+                            GeneratorKind::WaveFunctionCollapse
+                                | GeneratorKind::Sketch
+                                | GeneratorKind::Image
+                        ),
+                },
+                &playback,
+            );
         });
 
         Ok(())
     }
 
-    fn generate(map: Arc<RwLock<Map>>, iterations: u16, dimensions: (u16, u16)) {
-        MapState::GENERATOR.populate(map.clone(), dimensions);
-        for _ in 0..iterations {
-            MapState::GENERATOR.smooth(map.clone(), dimensions);
+    fn generate<G: MapGenerator>(
+        generator: &G,
+        map: Arc<RwLock<Map>>,
+        iterations: u16,
+        dimensions: (u16, u16),
+        seed: u64,
+        playback: &Playback,
+    ) {
+        generator.populate(map.clone(), dimensions, seed, playback);
+        playback.checkpoint(PlaybackGranularity::Phase);
+        for i in 0..iterations {
+            generator.smooth(map.clone(), dimensions, seed, i, playback);
+            playback.checkpoint(PlaybackGranularity::Phase);
+        }
+    }
+
+    // everything that is generated from the finished terrain, regardless of the generator
+    #[allow(clippy::too_many_arguments)]
+    fn generate_features(
+        config: &GeneratorConfig,
+        map: &Arc<RwLock<Map>>,
+        underground: &Arc<RwLock<Map>>,
+        dimensions: (u16, u16),
+        seed: u64,
+        erosion_iterations: u16,
+        provided: Provided,
+        playback: &Playback,
+    ) {
+        if !provided.elevation {
+            if let Err(e) = elevation::derive(map, dimensions, seed, &config.elevation) {
+                eprintln!("failed to derive the elevation: {e}");
+            }
+        }
+        if !provided.climate {
+            let reclassify = !provided.environments;
+            if let Err(e) = climate::simulate(map, dimensions, seed, &config.climate, reclassify) {
+                eprintln!("failed to simulate the climate: {e}");
+            }
+        }
+        // imported elevations are kept as they are (so that exported heightmaps are imported
+        // unchanged), only the rivers are traced along them
+        let erosion_iterations = if provided.elevation {
+            0
+        } else {
+            erosion_iterations
+        };
+        if let Err(e) = erosion::erode(
+            map,
+            dimensions,
+            erosion_iterations,
+            &config.erosion,
+            config.undersea.max_lake_size,
+            !provided.environments,
+        ) {
+            eprintln!("failed to erode the terrain: {e}");
+        }
+        if !provided.environments {
+            if let Err(e) = undersea::classify(map, dimensions, seed, &config.undersea) {
+                eprintln!("failed to divide the sea into zones: {e}");
+            }
+        }
+        playback.checkpoint(PlaybackGranularity::Phase);
+        let urban = !provided.environments;
+        if let Err(e) = settlements::place(map, dimensions, seed, &config.settlements, urban) {
+            eprintln!("failed to place settlements: {e}");
+        }
+        playback.checkpoint(PlaybackGranularity::Phase);
+        if let Err(e) = roads::build(map, dimensions, &config.roads) {
+            eprintln!("failed to build roads: {e}");
+        }
+        playback.checkpoint(PlaybackGranularity::Phase);
+        if let Err(e) = underground::generate(
+            map,
+            underground,
+            dimensions,
+            seed,
+            &config.underground,
+            playback,
+        ) {
+            eprintln!("failed to generate the underground: {e}");
+        }
+        for layer in [map, underground] {
+            if let Err(e) = areas::detect(layer, config.undersea.max_lake_size) {
+                eprintln!("failed to detect the areas: {e}");
+            }
+        }
+        let named = names::load_vocabulary(&config.names)
+            .and_then(|vocabulary| names::name(map, seed, &config.names, &vocabulary));
+        if let Err(e) = named {
+            eprintln!("failed to name the map: {e}");
         }
+        playback.checkpoint(PlaybackGranularity::Phase);
     }
 }
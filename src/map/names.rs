@@ -0,0 +1,563 @@
+use crate::config::NamesConfig;
+use crate::map::areas::AreaKind;
+use crate::map::chunks::{chunk_rng, NAME_PHASE};
+use crate::map::environments::Environment;
+use crate::map::settlements::SettlementSize;
+use crate::map::{grid, Hex, Map};
+use rand::Rng;
+use serde::Deserialize;
+use std::cmp::Reverse;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fs;
+use std::sync::{Arc, RwLock};
+
+// marks the start and the end of a name in the Markov chain
+const BOUNDARY: char = '\0';
+// names that are too short, copies of a sample or already taken are made up again this often
+const ATTEMPTS: usize = 100;
+
+#[derive(Clone, Debug)]
+pub struct Label {
+    pub text: String,
+    // the hex the label is centred on, the names of settlements are placed next to them instead
+    pub position: (usize, usize),
+    pub kind: LabelKind,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum LabelKind {
+    Landmass,
+    // seas and lakes
+    Water,
+    Environment,
+    River,
+    Settlement(SettlementSize),
+}
+
+impl LabelKind {
+    // labels of a lower rank are placed first, so they are the ones kept if labels overlap
+    fn rank(self) -> u8 {
+        match self {
+            LabelKind::Landmass => 0,
+            LabelKind::Settlement(SettlementSize::City) => 1,
+            LabelKind::Water => 2,
+            LabelKind::Environment => 3,
+            LabelKind::Settlement(SettlementSize::Town) => 4,
+            LabelKind::River => 5,
+            LabelKind::Settlement(SettlementSize::Village) => 6,
+        }
+    }
+}
+
+// the cultures and patterns of a vocabulary file (see `config/names.toml`)
+pub struct Vocabulary {
+    cultures: Vec<Culture>,
+    patterns: Patterns,
+}
+
+struct Culture {
+    // the environments the culture settles in rather than others
+    environments: Vec<Environment>,
+    chain: MarkovChain,
+    // in lowercase
+    samples: Vec<String>,
+    // in letters, made up names are as long as the samples
+    min_length: usize,
+    max_length: usize,
+}
+
+struct Patterns {
+    landmass: Vec<String>,
+    sea: Vec<String>,
+    lake: Vec<String>,
+    river: Vec<String>,
+    village: Vec<String>,
+    town: Vec<String>,
+    city: Vec<String>,
+    environments: HashMap<Environment, Vec<String>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct VocabularyFile {
+    cultures: BTreeMap<String, CultureEntry>,
+    patterns: PatternsEntry,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CultureEntry {
+    #[serde(default)]
+    environments: Vec<String>,
+    samples: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PatternsEntry {
+    landmass: Vec<String>,
+    sea: Vec<String>,
+    lake: Vec<String>,
+    river: Vec<String>,
+    village: Vec<String>,
+    town: Vec<String>,
+    city: Vec<String>,
+    #[serde(default)]
+    environments: HashMap<String, Vec<String>>,
+}
+
+// the letters following every sequence of `order` letters in the samples, as often as they do
+struct MarkovChain {
+    order: usize,
+    followers: HashMap<Vec<char>, Vec<char>>,
+}
+
+impl MarkovChain {
+    fn train(samples: &[String], order: usize) -> MarkovChain {
+        let mut followers: HashMap<Vec<char>, Vec<char>> = HashMap::new();
+        for sample in samples {
+            let mut letters = vec![BOUNDARY; order];
+            letters.extend(sample.chars());
+            letters.push(BOUNDARY);
+            for window in letters.windows(order + 1) {
+                followers
+                    .entry(window[..order].to_vec())
+                    .or_default()
+                    .push(window[order]);
+            }
+        }
+        MarkovChain { order, followers }
+    }
+
+    // `None` if the name gets longer than `max_length` letters
+    fn generate<R: Rng>(&self, rng: &mut R, max_length: usize) -> Option<String> {
+        let mut letters = vec![BOUNDARY; self.order];
+        loop {
+            let followers = self.followers.get(&letters[letters.len() - self.order..])?;
+            let next = followers[rng.gen_range(0..followers.len())];
+            if next == BOUNDARY {
+                return Some(letters[self.order..].iter().collect());
+            }
+            letters.push(next);
+            if letters.len() - self.order > max_length {
+                return None;
+            }
+        }
+    }
+}
+
+impl Culture {
+    // a new name following the pattern that is not taken yet
+    fn make_up<R: Rng>(&self, rng: &mut R, pattern: &str, taken: &HashSet<String>) -> String {
+        for attempt in 0..ATTEMPTS {
+            let word = match self.chain.generate(rng, self.max_length) {
+                Some(word) => word,
+                None => continue,
+            };
+            let name = pattern.replace("{name}", &capitalise(&word));
+            // cultures with few samples run out of new names, copies are better than duplicates
+            let copy = attempt < ATTEMPTS / 2 && self.samples.contains(&word);
+            if word.chars().count() >= self.min_length && !copy && !taken.contains(&name) {
+                return name;
+            }
+        }
+        // one of the samples that isn't taken yet, or a numbered one once they all are
+        let names: Vec<String> = self
+            .samples
+            .iter()
+            .map(|sample| pattern.replace("{name}", &capitalise(sample)))
+            .filter(|name| !taken.contains(name))
+            .collect();
+        if !names.is_empty() {
+            return names[rng.gen_range(0..names.len())].clone();
+        }
+        let sample = &self.samples[rng.gen_range(0..self.samples.len())];
+        let name = pattern.replace("{name}", &capitalise(sample));
+        (2..)
+            .map(|n| format!("{name} {n}"))
+            .find(|numbered| !taken.contains(numbered))
+            .unwrap_or(name)
+    }
+}
+
+// Reads the vocabulary file, which is done whenever a map is generated like for the sketch
+pub fn load_vocabulary(config: &NamesConfig) -> Result<Vocabulary, String> {
+    let text = fs::read_to_string(&config.path)
+        .map_err(|e| format!("failed to read the vocabulary '{}': {e}", config.path))?;
+    let file: VocabularyFile =
+        toml::from_str(&text).map_err(|e| format!("invalid vocabulary '{}': {e}", config.path))?;
+
+    if file.cultures.is_empty() {
+        return Err(String::from("the vocabulary requires at least one culture"));
+    }
+    let mut cultures = Vec::new();
+    for (key, culture) in file.cultures {
+        let samples: Vec<String> = culture
+            .samples
+            .iter()
+            .map(|s| s.trim().to_lowercase())
+            .filter(|s| !s.is_empty())
+            .collect();
+        let lengths = samples.iter().map(|s| s.chars().count());
+        let (min_length, max_length) = match (lengths.clone().min(), lengths.max()) {
+            (Some(min), Some(max)) => (min, max),
+            _ => return Err(format!("the culture '{key}' requires sample names")),
+        };
+        let environments = culture
+            .environments
+            .iter()
+            .map(|e| Environment::from_key(e).ok_or_else(|| format!("unknown environment '{e}'")))
+            .collect::<Result<_, _>>()?;
+        cultures.push(Culture {
+            environments,
+            chain: MarkovChain::train(&samples, config.order),
+            samples,
+            min_length,
+            max_length,
+        });
+    }
+
+    let entry = file.patterns;
+    let mut environments = HashMap::new();
+    for (key, patterns) in entry.environments {
+        let environment =
+            Environment::from_key(&key).ok_or_else(|| format!("unknown environment '{key}'"))?;
+        environments.insert(environment, patterns);
+    }
+    let patterns = Patterns {
+        landmass: entry.landmass,
+        sea: entry.sea,
+        lake: entry.lake,
+        river: entry.river,
+        village: entry.village,
+        town: entry.town,
+        city: entry.city,
+        environments,
+    };
+    let all = [
+        &patterns.landmass,
+        &patterns.sea,
+        &patterns.lake,
+        &patterns.river,
+        &patterns.village,
+        &patterns.town,
+        &patterns.city,
+    ];
+    for list in all.into_iter().chain(patterns.environments.values()) {
+        if list.is_empty() {
+            return Err(String::from(
+                "every kind of area requires at least one pattern",
+            ));
+        }
+        if let Some(pattern) = list.iter().find(|p| !p.contains("{name}")) {
+            return Err(format!("the pattern '{pattern}' doesn't contain {{name}}"));
+        }
+    }
+
+    Ok(Vocabulary { cultures, patterns })
+}
+
+// Names the areas (see `Areas`), rivers and settlements of the finished map and adds their labels.
+// Every landmass is settled by a culture picked by how much of it consists of the environments of
+// the culture, seas and lakes take the culture of the landmass they border the most. The surface is
+// named only, the underground has no cultures of its own
+pub fn name(
+    map: &Arc<RwLock<Map>>,
+    seed: u64,
+    config: &NamesConfig,
+    vocabulary: &Vocabulary,
+) -> Result<(), String> {
+    let mut rng = chunk_rng(seed, NAME_PHASE, 0);
+    let patterns = &vocabulary.patterns;
+    let mut taken = HashSet::new();
+    // the labels with their size, the larger ones of a kind are placed first
+    let mut labels: Vec<(Label, usize)> = Vec::new();
+    let mut area_names = Vec::new();
+    let mut settlement_names = Vec::new();
+
+    {
+        let map = map.read().map_err(|e| e.to_string())?;
+        let body_cultures = cultures(&map, &vocabulary.cultures, &mut rng)?;
+        let culture_at = |index: (usize, usize)| {
+            let body = map.areas.body_at(index).map(|area| area.id);
+            let culture = body.and_then(|id| body_cultures.get(&id)).copied();
+            &vocabulary.cultures[culture.unwrap_or(0)]
+        };
+
+        for area in &map.areas.areas {
+            let (list, min_size, kind) = match area.kind {
+                AreaKind::Landmass => (
+                    &patterns.landmass,
+                    config.min_landmass_size,
+                    LabelKind::Landmass,
+                ),
+                AreaKind::Sea => (&patterns.sea, config.min_sea_size, LabelKind::Water),
+                AreaKind::Lake => (&patterns.lake, config.min_lake_size, LabelKind::Water),
+                AreaKind::Environment(environment) => match patterns.environments.get(&environment)
+                {
+                    Some(list) => (list, config.min_environment_size, LabelKind::Environment),
+                    None => continue,
+                },
+            };
+            if area.size < min_size {
+                continue;
+            }
+            let pattern = &list[rng.gen_range(0..list.len())];
+            let name = culture_at(area.first).make_up(&mut rng, pattern, &taken);
+            taken.insert(name.clone());
+            area_names.push((area.id, name.clone()));
+            let label = Label {
+                text: name,
+                position: area.centre,
+                kind,
+            };
+            labels.push((label, area.size));
+        }
+
+        for river in rivers(&map.tiles) {
+            if river.length < config.min_river_length {
+                continue;
+            }
+            let list = &patterns.river;
+            let pattern = &list[rng.gen_range(0..list.len())];
+            let name = culture_at(river.mouth).make_up(&mut rng, pattern, &taken);
+            taken.insert(name.clone());
+            let label = Label {
+                text: name,
+                position: river.centre,
+                kind: LabelKind::River,
+            };
+            labels.push((label, river.length));
+        }
+
+        for settlement in &map.settlements {
+            let list = match settlement.size {
+                SettlementSize::Village => &patterns.village,
+                SettlementSize::Town => &patterns.town,
+                SettlementSize::City => &patterns.city,
+            };
+            let pattern = &list[rng.gen_range(0..list.len())];
+            let name = culture_at(settlement.position).make_up(&mut rng, pattern, &taken);
+            taken.insert(name.clone());
+            settlement_names.push(name.clone());
+            let label = Label {
+                text: name,
+                position: settlement.position,
+                kind: LabelKind::Settlement(settlement.size),
+            };
+            labels.push((label, settlement.population as usize));
+        }
+    }
+
+    labels.sort_by_key(|(label, size)| (label.kind.rank(), Reverse(*size)));
+    let mut map = map.write().map_err(|e| e.to_string())?;
+    for (id, name) in area_names {
+        map.areas.areas[id as usize].name = Some(name);
+    }
+    for (settlement, name) in map.settlements.iter_mut().zip(settlement_names) {
+        settlement.name = Some(name);
+    }
+    map.labels = labels.into_iter().map(|(label, _)| label).collect();
+    eprintln!("Named {} places", map.labels.len());
+    Ok(())
+}
+
+// the culture of every landmass, sea and lake by the id of the area
+fn cultures<R: Rng>(
+    map: &Map,
+    cultures: &[Culture],
+    rng: &mut R,
+) -> Result<HashMap<u32, usize>, String> {
+    let height = map.tiles.len();
+    let width = map.tiles.first().map_or(0, |row| row.len());
+    // the hexes of every environment of every landmass, and the land hexes bordering every sea or
+    // lake by landmass
+    let mut environments: HashMap<u32, HashMap<Environment, usize>> = HashMap::new();
+    let mut shores: HashMap<u32, BTreeMap<u32, usize>> = HashMap::new();
+    let missing = || String::from("the areas of the map have not been detected");
+    for y in 0..height {
+        for x in 0..width {
+            let hex = map.tiles[y][x];
+            let body = map.areas.body_at((x, y)).ok_or_else(missing)?;
+            if !hex.environment.is_water() {
+                let counts = environments.entry(body.id).or_default();
+                *counts.entry(hex.environment).or_default() += 1;
+                continue;
+            }
+            for index in grid::neighbours((x, y), (width, height)) {
+                let other = map.areas.body_at(index).ok_or_else(missing)?;
+                if other.kind == AreaKind::Landmass {
+                    *shores
+                        .entry(body.id)
+                        .or_default()
+                        .entry(other.id)
+                        .or_default() += 1;
+                }
+            }
+        }
+    }
+
+    let mut result = HashMap::new();
+    for area in map.areas.of_kind(AreaKind::Landmass) {
+        let counts = &environments[&area.id];
+        let weights: Vec<usize> = cultures
+            .iter()
+            .map(|c| c.environments.iter().filter_map(|e| counts.get(e)).sum())
+            .collect();
+        let total: usize = weights.iter().sum();
+        let culture = if total == 0 {
+            rng.gen_range(0..cultures.len())
+        } else {
+            let mut roll = rng.gen_range(0..total);
+            weights
+                .iter()
+                .position(|&weight| {
+                    let hit = roll < weight;
+                    roll = roll.saturating_sub(weight);
+                    hit
+                })
+                .unwrap_or(0)
+        };
+        result.insert(area.id, culture);
+    }
+    for area in &map.areas.areas {
+        if !matches!(area.kind, AreaKind::Sea | AreaKind::Lake) {
+            continue;
+        }
+        // the first of several landmasses bordering it equally much
+        let landmass = shores.get(&area.id).and_then(|shore| {
+            shore
+                .iter()
+                .max_by_key(|&(&id, &count)| (count, Reverse(id)))
+                .map(|(id, _)| *id)
+        });
+        let culture = match landmass {
+            Some(id) => result[&id],
+            None => rng.gen_range(0..cultures.len()),
+        };
+        result.insert(area.id, culture);
+    }
+    Ok(result)
+}
+
+struct River {
+    // the lowest hex of the river
+    mouth: (usize, usize),
+    // the middle hex of the longest branch, where the name is placed
+    centre: (usize, usize),
+    // of the longest branch, in hexes
+    length: usize,
+}
+
+// the river networks, hexes belong to the same one if a river crosses the edge between them
+fn rivers(tiles: &[Vec<Hex>]) -> Vec<River> {
+    let height = tiles.len();
+    let width = tiles.first().map_or(0, |row| row.len());
+    let dimensions = (width, height);
+    let connected = |index: (usize, usize)| {
+        let rivers = tiles[index.1][index.0].rivers;
+        grid::DIRECTIONS.into_iter().filter_map(move |direction| {
+            if rivers & (1 << direction) == 0 {
+                return None;
+            }
+            let (x, y) = grid::neighbour(index, direction, dimensions)?;
+            let opposite = 1 << grid::opposite(direction);
+            (tiles[y][x].rivers & opposite != 0).then_some((x, y))
+        })
+    };
+
+    let mut visited = vec![vec![false; width]; height];
+    let mut rivers = Vec::new();
+    for y in 0..height {
+        for x in 0..width {
+            if visited[y][x] || tiles[y][x].rivers == 0 {
+                continue;
+            }
+            visited[y][x] = true;
+            let mut network = vec![(x, y)];
+            let mut queue = VecDeque::from([(x, y)]);
+            while let Some(index) = queue.pop_front() {
+                for (n_x, n_y) in connected(index) {
+                    if !visited[n_y][n_x] {
+                        visited[n_y][n_x] = true;
+                        network.push((n_x, n_y));
+                        queue.push_back((n_x, n_y));
+                    }
+                }
+            }
+
+            // the longest branch leads from the mouth to the hex furthest away from it
+            let mut mouth = network[0];
+            for &(n_x, n_y) in &network {
+                if tiles[n_y][n_x].elevation < tiles[mouth.1][mouth.0].elevation {
+                    mouth = (n_x, n_y);
+                }
+            }
+            let mut previous = HashMap::from([(mouth, mouth)]);
+            let mut queue = VecDeque::from([mouth]);
+            let mut source = mouth;
+            while let Some(index) = queue.pop_front() {
+                source = index;
+                for neighbour in connected(index) {
+                    if let Entry::Vacant(entry) = previous.entry(neighbour) {
+                        entry.insert(index);
+                        queue.push_back(neighbour);
+                    }
+                }
+            }
+            let mut branch = vec![source];
+            while branch[branch.len() - 1] != mouth {
+                branch.push(previous[&branch[branch.len() - 1]]);
+            }
+            rivers.push(River {
+                mouth,
+                centre: branch[branch.len() / 2],
+                length: branch.len(),
+            });
+        }
+    }
+    rivers
+}
+
+// the first letter of every word in uppercase
+fn capitalise(name: &str) -> String {
+    let mut result = String::with_capacity(name.len());
+    let mut start = true;
+    for c in name.chars() {
+        if start {
+            result.extend(c.to_uppercase());
+        } else {
+            result.push(c);
+        }
+        start = c == ' ' || c == '-';
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    #[test]
+    fn made_up_names_are_never_taken() {
+        // the chain of a single sample only ever repeats it
+        let samples = vec![String::from("arden")];
+        let culture = Culture {
+            environments: Vec::new(),
+            chain: MarkovChain::train(&samples, 2),
+            samples,
+            min_length: 5,
+            max_length: 5,
+        };
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let mut taken = HashSet::new();
+        for expected in ["Arden Hills", "Arden Hills 2", "Arden Hills 3"] {
+            let name = culture.make_up(&mut rng, "{name} Hills", &taken);
+            assert_eq!(name, expected);
+            taken.insert(name);
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+const DEFAULT_DELAY: Duration = Duration::from_millis(50);
+const MAX_DELAY: Duration = Duration::from_millis(2000);
+
+// ordered from finest to coarsest, a checkpoint only halts if it is at least as coarse as the
+// selected granularity
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug)]
+pub enum PlaybackGranularity {
+    Hex,
+    Row,
+    Phase,
+}
+
+impl PlaybackGranularity {
+    fn next(self) -> PlaybackGranularity {
+        match self {
+            PlaybackGranularity::Hex => PlaybackGranularity::Row,
+            PlaybackGranularity::Row => PlaybackGranularity::Phase,
+            PlaybackGranularity::Phase => PlaybackGranularity::Hex,
+        }
+    }
+}
+
+// Allows intentionally slowing down the generation so that the individual steps become visible.
+// The generators report their progress via `checkpoint`, which (depending on the current settings)
+// either returns immediately, sleeps for the configured delay or blocks until the user steps/resumes
+pub struct Playback {
+    state: Mutex<PlaybackState>,
+    resumed: Condvar,
+}
+
+struct PlaybackState {
+    enabled: bool,
+    paused: bool,
+    // amount of checkpoints that may be passed while paused
+    pending_steps: u32,
+    granularity: PlaybackGranularity,
+    delay: Duration,
+}
+
+impl Default for Playback {
+    fn default() -> Self {
+        Playback::new()
+    }
+}
+
+impl Playback {
+    pub fn new() -> Playback {
+        Playback {
+            state: Mutex::new(PlaybackState {
+                enabled: false,
+                paused: false,
+                pending_steps: 0,
+                granularity: PlaybackGranularity::Row,
+                delay: DEFAULT_DELAY,
+            }),
+            resumed: Condvar::new(),
+        }
+    }
+
+    pub fn checkpoint(&self, granularity: PlaybackGranularity) {
+        let mut state = match self.state.lock() {
+            Ok(s) => s,
+            Err(e) => {
+                eprintln!("Failed to access playback state: {e}");
+                return;
+            }
+        };
+        if !state.enabled || granularity < state.granularity {
+            return;
+        }
+
+        if !state.paused {
+            let delay = state.delay;
+            drop(state);
+            std::thread::sleep(delay);
+            return;
+        }
+
+        while state.enabled && state.paused && state.pending_steps == 0 {
+            state = match self.resumed.wait(state) {
+                Ok(s) => s,
+                Err(e) => {
+                    eprintln!("Failed to wait for playback: {e}");
+                    return;
+                }
+            };
+        }
+        if state.paused && state.pending_steps > 0 {
+            state.pending_steps -= 1;
+        }
+    }
+
+    // whether checkpoints of the given granularity currently slow down the generation
+    pub fn is_visible(&self, granularity: PlaybackGranularity) -> bool {
+        match self.state.lock() {
+            Ok(state) => state.enabled && granularity >= state.granularity,
+            Err(_) => false,
+        }
+    }
+
+    pub fn toggle_enabled(&self) {
+        self.update(|state| {
+            state.enabled = !state.enabled;
+            state.pending_steps = 0;
+        });
+    }
+
+    pub fn toggle_paused(&self) {
+        self.update(|state| {
+            state.paused = !state.paused;
+            state.pending_steps = 0;
+        });
+    }
+
+    // lets the generation continue until the next checkpoint of the selected granularity
+    pub fn step(&self) {
+        self.update(|state| {
+            if state.paused {
+                state.pending_steps += 1;
+            }
+        });
+    }
+
+    pub fn cycle_granularity(&self) {
+        self.update(|state| state.granularity = state.granularity.next());
+    }
+
+    pub fn faster(&self) {
+        self.update(|state| {
+            state.delay /= 2;
+            if state.delay < Duration::from_millis(1) {
+                state.delay = Duration::ZERO;
+            }
+        });
+    }
+
+    pub fn slower(&self) {
+        self.update(|state| {
+            state.delay = if state.delay.is_zero() {
+                Duration::from_millis(1)
+            } else {
+                (state.delay * 2).min(MAX_DELAY)
+            };
+        });
+    }
+
+    fn update<F: FnOnce(&mut PlaybackState)>(&self, f: F) {
+        let mut state = match self.state.lock() {
+            Ok(s) => s,
+            Err(e) => {
+                eprintln!("Failed to update playback state: {e}");
+                return;
+            }
+        };
+        f(&mut state);
+        eprintln!(
+            "Playback: {}, {}, granularity: {:?}, delay per step: {:?}",
+            if state.enabled { "enabled" } else { "disabled" },
+            if state.paused { "paused" } else { "running" },
+            state.granularity,
+            state.delay,
+        );
+        self.resumed.notify_all();
+    }
+}
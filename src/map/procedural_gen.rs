@@ -1,109 +1,372 @@
+use crate::config::{band_rows, GeneratorConfig};
+use crate::map::chunks::{chunk_rng, row_chunks, snapshot, ChunkWriter, BOUNDARY_PHASE};
 use crate::map::environments::Environment;
-use crate::map::{Map, MapGenerator};
+use crate::map::grid;
+use crate::map::playback::Playback;
+use crate::map::tectonics::{Influence, Tectonics};
+use crate::map::{Hex, Map, MapGenerator};
 use rand::Rng;
-use std::cmp::{max, min};
+use rand_chacha::ChaCha8Rng;
+use rayon::prelude::*;
+use std::cmp::min;
+use std::collections::HashMap;
+use std::ops::Range;
 use std::sync::{Arc, RwLock};
 
-pub struct ProceduralGenerator {}
+// rows generated (and thrown away) above the boundary rows between chunks
+const WARM_UP_ROWS: usize = 4;
+
+#[derive(Clone)]
+pub struct ProceduralGenerator {
+    // chance in percent for a hex to take on the environment most of its neighbours share when
+    // smoothing the map
+    pub smoothing_strength: u8,
+    pub config: Arc<GeneratorConfig>,
+}
 
-// TODO: doesn't feel much better than the random generator yet
-//  probably needs some smoothing for bigger clusters of the same environment
 impl MapGenerator for ProceduralGenerator {
-    fn populate(&self, map: Arc<RwLock<Map>>, dimensions: (u16, u16)) {
-        let (max_x, max_y) = ((dimensions.0 - 1) as usize, (dimensions.1 - 1) as usize);
+    fn populate(
+        &self,
+        map: Arc<RwLock<Map>>,
+        dimensions: (u16, u16),
+        seed: u64,
+        playback: &Playback,
+    ) {
+        let dimensions = (dimensions.0 as usize, dimensions.1 as usize);
+        let chunks = row_chunks(dimensions.1);
+        let writer = ChunkWriter::new(&map, playback);
+        let tectonics_config = &self.config.procedural.tectonics;
+        let tectonics = tectonics_config
+            .enabled
+            .then(|| Tectonics::simulate(dimensions, seed, tectonics_config));
+        let tectonics = tectonics.as_ref();
 
-        for y in 0..=max_y {
-            for x in 0..=max_x {
-                ProceduralGenerator::generate_hex(map.clone(), x, y, max_x, max_y)
-            }
-        }
+        // Every hex depends on the hexes above/left of it, so the row above every chunk (the
+        // boundary) is generated first. It continues from a few rows that are thrown away, so that
+        // it doesn't look like the top edge of the map. The chunks then continue from the boundary
+        // above them and their last row also takes the boundary below into account
+        let boundaries: Vec<Option<Vec<Hex>>> = chunks
+            .par_iter()
+            .enumerate()
+            .map(|(i, rows)| {
+                let y = rows.start.checked_sub(1)?;
+                let mut rng = chunk_rng(seed, BOUNDARY_PHASE, i);
+                let mut above = None;
+                for warm_up in y.saturating_sub(WARM_UP_ROWS)..y {
+                    let row = self.generate_row(
+                        &mut rng,
+                        warm_up,
+                        (above.as_ref(), None),
+                        dimensions,
+                        tectonics,
+                        None,
+                    );
+                    above = Some(row);
+                }
+                let row = self.generate_row(
+                    &mut rng,
+                    y,
+                    (above.as_ref(), None),
+                    dimensions,
+                    tectonics,
+                    Some(&writer),
+                );
+                writer.row_done(y, &row);
+                Some(row)
+            })
+            .collect();
+        chunks.par_iter().enumerate().for_each(|(i, rows)| {
+            let above = boundaries[i].as_ref();
+            let below = boundaries.get(i + 1).and_then(|b| b.as_ref());
+            // the last row of the chunk is the boundary of the next one
+            let rows = rows.start..rows.end - below.map_or(0, |_| 1);
+            let rng = chunk_rng(seed, 0, i);
+            self.generate_chunk(&writer, rng, rows, (above, below), dimensions, tectonics);
+        });
     }
 
-    fn smooth(&self, _map: Arc<RwLock<Map>>, _dimensions: (u16, u16)) {
-        todo!() //TODO: Not sure yet if this needs to do anything
+    fn smooth(
+        &self,
+        map: Arc<RwLock<Map>>,
+        dimensions: (u16, u16),
+        seed: u64,
+        iteration: u16,
+        playback: &Playback,
+    ) {
+        // every hex is smoothed based on the state before this iteration
+        let tiles = match snapshot(&map) {
+            Ok(t) => t,
+            Err(e) => {
+                eprintln!("Failed to smooth map: {e}");
+                return;
+            }
+        };
+        let dimensions = (dimensions.0 as usize, dimensions.1 as usize);
+        let writer = ChunkWriter::new(&map, playback);
+
+        row_chunks(dimensions.1)
+            .into_par_iter()
+            .enumerate()
+            .for_each(|(i, rows)| {
+                let mut rng = chunk_rng(seed, iteration as u32 + 1, i);
+                for y in rows {
+                    let mut row = tiles[y].clone();
+                    for (x, hex) in row.iter_mut().enumerate() {
+                        hex.environment =
+                            self.smoothed_environment(&mut rng, &tiles, (x, y), dimensions);
+                        writer.hex_done(x, y, *hex);
+                    }
+                    writer.row_done(y, &row);
+                }
+            });
     }
 }
 
 impl ProceduralGenerator {
-    fn generate_hex(map: Arc<RwLock<Map>>, x: usize, y: usize, max_x: usize, max_y: usize) {
-        let water_odds: u32 = if ProceduralGenerator::is_mostly_land(
-            ProceduralGenerator::surrounding_environments(map.clone(), x, y, max_x, max_y),
-        ) {
-            4
+    // cellular automaton step: a hex changes to the environment the majority of its neighbours
+    // share, if at least half of them agree and that environment can exist at the hex's latitude
+    fn smoothed_environment(
+        &self,
+        rng: &mut ChaCha8Rng,
+        tiles: &[Vec<Hex>],
+        index: (usize, usize),
+        dimensions: (usize, usize),
+    ) -> Environment {
+        let (x, y) = index;
+        let current = tiles[y][x].environment;
+        let neighbours = grid::neighbours(index, dimensions);
+
+        // ties are broken by the order of the environments to stay deterministic
+        let (majority, count) = neighbours
+            .iter()
+            .fold(
+                HashMap::<Environment, usize>::new(),
+                |mut counts, &(n_x, n_y)| {
+                    *counts.entry(tiles[n_y][n_x].environment).or_default() += 1;
+                    counts
+                },
+            )
+            .into_iter()
+            .max_by_key(|&(env, cnt)| (cnt, env))
+            .unwrap_or((current, 0));
+
+        if majority == current
+            || count * 2 < neighbours.len()
+            || !self.allowed_at_latitude(majority, y, dimensions.1 - 1)
+        {
+            return current;
+        }
+
+        if rng.gen_ratio(self.smoothing_strength.min(100) as u32, 100) {
+            majority
+        } else {
+            current
+        }
+    }
+
+    // keeps the smoothing from spreading environments beyond the latitudes they are generated at,
+    // e.g. deserts creeping into the poles
+    fn allowed_at_latitude(&self, environment: Environment, y: usize, max_y: usize) -> bool {
+        let procedural = &self.config.procedural;
+        match environment {
+            Environment::DESERT => {
+                (max_y / 2).abs_diff(y) < band_rows(procedural.land.desert_band, max_y)
+            }
+            Environment::ARCTIC | Environment::GLACIER => {
+                min(y, max_y - y) < band_rows(procedural.ice.band, max_y)
+            }
+            _ => true,
+        }
+    }
+
+    fn generate_chunk(
+        &self,
+        writer: &ChunkWriter,
+        mut rng: ChaCha8Rng,
+        rows: Range<usize>,
+        // the boundary rows directly above/below the chunk, if any
+        boundaries: (Option<&Vec<Hex>>, Option<&Vec<Hex>>),
+        dimensions: (usize, usize),
+        tectonics: Option<&Tectonics>,
+    ) {
+        let (above, below) = boundaries;
+        let mut previous: Option<Vec<Hex>> = None;
+        for y in rows.clone() {
+            let above = previous.as_ref().or(above);
+            let below = below.filter(|_| y + 1 == rows.end);
+            let row = self.generate_row(
+                &mut rng,
+                y,
+                (above, below),
+                dimensions,
+                tectonics,
+                Some(writer),
+            );
+            writer.row_done(y, &row);
+            previous = Some(row);
+        }
+    }
+
+    // the hexes are only reported to the writer if there is one
+    fn generate_row(
+        &self,
+        rng: &mut ChaCha8Rng,
+        y: usize,
+        // the rows above/below, if they have already been generated
+        neighbours: (Option<&Vec<Hex>>, Option<&Vec<Hex>>),
+        dimensions: (usize, usize),
+        tectonics: Option<&Tectonics>,
+        writer: Option<&ChunkWriter>,
+    ) -> Vec<Hex> {
+        let (width, height) = dimensions;
+        let (max_x, max_y) = (width - 1, height - 1);
+        let (above, below) = neighbours;
+
+        let mut row = vec![Hex::EMPTY; width];
+        for x in 0..=max_x {
+            let mut surroundings =
+                ProceduralGenerator::surrounding_environments(above, &row, x, max_x);
+            // the same hexes as above
+            if let Some(below) = below {
+                surroundings.push(below[x].environment);
+                if x > 0 {
+                    surroundings.push(below[x - 1].environment);
+                }
+                if x < max_x {
+                    surroundings.push(below[x + 1].environment);
+                }
+            }
+            let influence = tectonics.map(|t| t.influence((x, y)));
+            row[x].environment = self.generate_hex(rng, &surroundings, y, max_y, influence);
+            if let Some(influence) = influence {
+                row[x].elevation = influence.uplift(&self.config.procedural.tectonics);
+            }
+            if let Some(writer) = writer {
+                writer.hex_done(x, y, row[x]);
+            }
+        }
+        row
+    }
+
+    fn generate_hex(
+        &self,
+        rng: &mut ChaCha8Rng,
+        surroundings: &Vec<Environment>,
+        y: usize,
+        max_y: usize,
+        influence: Option<Influence>,
+    ) -> Environment {
+        let water = &self.config.procedural.water;
+        let water_odds: u32 = if ProceduralGenerator::is_mostly_land(surroundings) {
+            water.odds_next_to_land
         } else {
-            7
+            water.odds_next_to_water
         };
 
         let polar_distance = min(y, max_y - y);
-        // only the top/bottom ~12% are covered in ice (so 24% total at most)
-        let close_to_pole = polar_distance < (max_y / 7);
+        let close_to_pole = polar_distance < band_rows(water.polar_band, max_y);
+        let bonus = if close_to_pole {
+            water.polar_bonus
+        } else {
+            water.bonus
+        };
+
+        let mut odds = water_odds + bonus;
+        if let Some(influence) = influence {
+            let tectonics = &self.config.procedural.tectonics;
+            if influence.stress > 0 {
+                odds = odds.saturating_sub(tectonics.land_odds * influence.stress as u32 / 100);
+            } else {
+                odds += tectonics.rift_water_odds * influence.stress.unsigned_abs() / 100;
+            }
+        }
 
-        if rand::thread_rng().gen_ratio(water_odds + if close_to_pole { 3 } else { 1 }, 10) {
-            ProceduralGenerator::generate_water(map, x, y, max_y);
+        if rng.gen_ratio(min(odds, 10), 10) {
+            self.generate_water(rng, y, max_y)
         } else {
-            ProceduralGenerator::generate_land(map, x, y, max_x, max_y);
+            self.generate_land(rng, surroundings, y, max_y, influence)
         }
     }
 
-    fn generate_water(map: Arc<RwLock<Map>>, x: usize, y: usize, max_y: usize) {
+    fn generate_water(&self, rng: &mut ChaCha8Rng, y: usize, max_y: usize) -> Environment {
+        let ice = &self.config.procedural.ice;
         let polar_distance = min(y as u32, (max_y - y) as u32);
-        // only the top/bottom ~12% are covered in ice (so 24% total at most)
-        let max_dist_for_ice = (max_y / 8) as u32;
+        let max_dist_for_ice = band_rows(ice.band, max_y) as u32;
 
         if polar_distance >= max_dist_for_ice {
-            return ProceduralGenerator::set_hex(map, Environment::AQUATIC, x, y);
+            return Environment::AQUATIC;
         }
 
-        // (8/10) * ((max_dist_for_ice-polar_distance) / max_dist_for_ice)
-        // so up to 80% chance for ice the closer to the poles we get
-        if !rand::thread_rng().gen_ratio(
-            8 * (max_dist_for_ice - polar_distance),
-            10 * max_dist_for_ice,
+        // max_freeze_chance * ((max_dist_for_ice-polar_distance) / max_dist_for_ice)
+        // so the chance for ice increases the closer to the poles we get
+        if !rng.gen_ratio(
+            min(ice.max_freeze_chance, 100) * (max_dist_for_ice - polar_distance),
+            100 * max_dist_for_ice,
         ) {
-            return ProceduralGenerator::set_hex(map, Environment::AQUATIC, x, y);
+            return Environment::AQUATIC;
         }
 
-        // up to 20% chance for glaciers the closer to the poles we get
-        if !rand::thread_rng().gen_ratio(
-            2 * (max_dist_for_ice - polar_distance),
-            10 * max_dist_for_ice,
+        if !rng.gen_ratio(
+            min(ice.max_arctic_chance, 100) * (max_dist_for_ice - polar_distance),
+            100 * max_dist_for_ice,
         ) {
-            return ProceduralGenerator::set_hex(map, Environment::GLACIER, x, y);
+            return Environment::GLACIER;
         }
-        return ProceduralGenerator::set_hex(map, Environment::ARCTIC, x, y);
+        Environment::ARCTIC
     }
 
-    fn generate_land(map: Arc<RwLock<Map>>, x: usize, y: usize, max_x: usize, max_y: usize) {
-        let equatorial_distance = (max_y / 2).abs_diff(y) as u32;
+    fn generate_land(
+        &self,
+        rng: &mut ChaCha8Rng,
+        surroundings: &Vec<Environment>,
+        y: usize,
+        max_y: usize,
+        influence: Option<Influence>,
+    ) -> Environment {
+        let land = &self.config.procedural.land;
 
-        // only the middle ~25% can generate deserts
-        let max_dist_for_desert = (max_y / 8) as u32;
+        // colliding plates fold up mountain chains, with volcanoes a bit further inland
+        if let Some(influence) = influence.filter(|i| i.stress > 0) {
+            let tectonics = &self.config.procedural.tectonics;
+            let stress = influence.stress as u32;
+            if influence.boundary_distance == tectonics.arc_distance
+                && rng.gen_ratio(min(tectonics.arc_volcanic_chance * stress / 100, 100), 100)
+            {
+                return Environment::VOLCANIC;
+            }
+            if rng.gen_ratio(min(tectonics.mountain_chance * stress / 100, 100), 100) {
+                return Environment::MOUNTAIN;
+            }
+        }
+
+        let equatorial_distance = (max_y / 2).abs_diff(y) as u32;
+        let max_dist_for_desert = band_rows(land.desert_band, max_y) as u32;
 
-        // up to 30% chance for deserts the closer to the equator we get
+        // the chance for deserts increases the closer to the equator we get
         if max_dist_for_desert > equatorial_distance
-            && rand::thread_rng().gen_ratio(
-                30 * (max_dist_for_desert - equatorial_distance),
+            && rng.gen_ratio(
+                min(land.max_desert_chance, 100) * (max_dist_for_desert - equatorial_distance),
                 100 * max_dist_for_desert,
             )
         {
-            return ProceduralGenerator::set_hex(map, Environment::DESERT, x, y);
+            return Environment::DESERT;
         }
 
-        let surroundings =
-            ProceduralGenerator::surrounding_environments(map.clone(), x, y, max_x, max_y);
         let aerial_count = ProceduralGenerator::count_in_surroundings(
-            &surroundings,
+            surroundings,
             &mut [Environment::AERIAL].iter(),
         );
         // make it less likely for more aerial terrain the more there already is in the surrounding area
-        // maximum 5% if nothing around it is aerial
-        if aerial_count <= 2 && rand::thread_rng().gen_ratio(max(0, 3 - aerial_count), 100) {
-            return ProceduralGenerator::set_hex(map, Environment::AERIAL, x, y);
+        let aerial_chance = land
+            .aerial_chance
+            .saturating_sub(land.aerial_chance_per_neighbour * aerial_count);
+        if aerial_chance > 0 && rng.gen_ratio(min(aerial_chance, 100), 100) {
+            return Environment::AERIAL;
         }
 
         // zones that have higher potential for volcanic activity
         let volcanic_zone_count = ProceduralGenerator::count_in_surroundings(
-            &surroundings,
+            surroundings,
             &mut [
                 Environment::MOUNTAIN,
                 Environment::DESERT,
@@ -111,27 +374,31 @@ impl ProceduralGenerator {
             ]
             .iter(),
         );
-        if rand::thread_rng().gen_ratio(max(0, 3 + 2 * volcanic_zone_count), 100) {
-            return ProceduralGenerator::set_hex(map, Environment::VOLCANIC, x, y);
+        let volcanic_chance =
+            land.volcanic_chance + land.volcanic_chance_per_neighbour * volcanic_zone_count;
+        // with plates, volcanoes only appear along their boundaries
+        if influence.is_none() && rng.gen_ratio(min(volcanic_chance, 100), 100) {
+            return Environment::VOLCANIC;
         }
 
-        // up to 40% chance for plains the further away we are from the equator (since
+        // the chance for plains increases the further away we are from the equator (since
         // equatorial_distance can at most be max_y/2)
-        if rand::thread_rng().gen_ratio(4 * equatorial_distance, 5 * max_y as u32) {
-            return ProceduralGenerator::set_hex(map, Environment::PLAINS, x, y);
+        if rng.gen_ratio(
+            2 * min(land.max_plains_chance, 100) * min(equatorial_distance, max_y as u32 / 2),
+            100 * max_y as u32,
+        ) {
+            return Environment::PLAINS;
         }
 
-        // 45% chance for forests
-        if rand::thread_rng().gen_ratio(9, 20) {
-            return ProceduralGenerator::set_hex(map, Environment::FOREST, x, y);
+        if rng.gen_ratio(min(land.forest_chance, 100), 100) {
+            return Environment::FOREST;
         }
 
-        // 65% chance for mountains
-        if rand::thread_rng().gen_ratio(13, 20) {
-            return ProceduralGenerator::set_hex(map, Environment::MOUNTAIN, x, y);
+        if rng.gen_ratio(min(land.mountain_chance, 100), 100) {
+            return Environment::MOUNTAIN;
         }
 
-        return ProceduralGenerator::set_hex(map, Environment::SWAMP, x, y);
+        Environment::SWAMP
     }
 
     fn count_in_surroundings<'a, I>(surroundings: &Vec<Environment>, envs: &mut I) -> u32
@@ -147,21 +414,9 @@ impl ProceduralGenerator {
         })
     }
 
-    fn set_hex(map: Arc<RwLock<Map>>, env: Environment, x: usize, y: usize) {
-        let mut map = match map.write() {
-            Ok(m) => m,
-            Err(e) => {
-                println!("failed to set hex: {e}");
-                return;
-            }
-        };
-
-        map.tiles[y][x].environment = env;
-    }
-
-    fn is_mostly_land(surroundings: Vec<Environment>) -> bool {
+    fn is_mostly_land(surroundings: &Vec<Environment>) -> bool {
         let cnt = ProceduralGenerator::count_in_surroundings(
-            &surroundings,
+            surroundings,
             &mut [
                 Environment::AQUATIC,
                 Environment::ARCTIC,
@@ -170,7 +425,7 @@ impl ProceduralGenerator {
             .iter(),
         );
         let none_cnt = ProceduralGenerator::count_in_surroundings(
-            &surroundings,
+            surroundings,
             &mut [Environment::NONE].iter(),
         );
         let size = surroundings.len() as u32;
@@ -179,36 +434,68 @@ impl ProceduralGenerator {
 
     // assumes that iterating over the map left->right first and top->bottom second
     fn surrounding_environments(
-        map: Arc<RwLock<Map>>,
+        above: Option<&Vec<Hex>>,
+        row: &[Hex],
         x: usize,
-        y: usize,
         max_x: usize,
-        _max_y: usize,
     ) -> Vec<Environment> {
         let mut surrounding_environments: Vec<Environment> = vec![];
-        let map = match map.read() {
-            Ok(m) => m,
-            Err(e) => {
-                println!("Failed to determine surrounding envs: {e}");
-                return vec![];
-            }
-        };
 
-        if y > 0 {
-            surrounding_environments.push(map.tiles[y - 1][x].environment);
+        if let Some(above) = above {
+            surrounding_environments.push(above[x].environment);
             if x > 0 {
-                surrounding_environments.push(map.tiles[y - 1][x - 1].environment);
+                surrounding_environments.push(above[x - 1].environment);
             }
             if x < max_x {
-                surrounding_environments.push(map.tiles[y - 1][x + 1].environment);
+                surrounding_environments.push(above[x + 1].environment);
             }
         }
         if x > 0 {
-            let _ = &map.tiles[y];
-            let _ = &map.tiles[y][x - 1];
-            surrounding_environments.push(map.tiles[y][x - 1].environment);
+            surrounding_environments.push(row[x - 1].environment);
         }
 
         surrounding_environments
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::map::MapState;
+
+    fn generate(threads: usize, seed: u64) -> Vec<Vec<Hex>> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap();
+        pool.install(|| {
+            // several chunks, the last one shorter than the others
+            let dimensions = (48, 70);
+            let map = Arc::new(RwLock::new(MapState::create_empty_map(dimensions).unwrap()));
+            let generator = ProceduralGenerator {
+                smoothing_strength: 40,
+                config: config::current(),
+            };
+            let playback = Playback::new();
+            generator.populate(map.clone(), dimensions, seed, &playback);
+            for iteration in 0..2 {
+                generator.smooth(map.clone(), dimensions, seed, iteration, &playback);
+            }
+            snapshot(&map).unwrap()
+        })
+    }
+
+    #[test]
+    fn same_map_regardless_of_thread_count() {
+        for seed in [1, 42] {
+            let single = generate(1, seed);
+            assert!(single
+                .iter()
+                .flatten()
+                .all(|h| h.environment != Environment::NONE));
+            assert!(single == generate(4, seed));
+            assert!(single == generate(7, seed));
+        }
+    }
+}
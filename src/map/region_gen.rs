@@ -0,0 +1,332 @@
+use crate::config::GeneratorConfig;
+use crate::map::chunks::{chunk_rng, row_chunks, snapshot, ChunkWriter};
+use crate::map::environments::Environment;
+use crate::map::grid;
+use crate::map::playback::Playback;
+use crate::map::random_gen::RandomGenerator;
+use crate::map::{Hex, Map, MapGenerator};
+use rand::Rng;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::sync::{Arc, RwLock};
+
+// A few selected hexes of a map, cut out to be generated again at a finer scale
+pub struct Region {
+    // the selected hexes of the parent map, sorted
+    pub selection: Vec<(usize, usize)>,
+    pub dimensions: (u16, u16),
+    scale: f32,
+    // position of the middle point of the region's hex (0, 0) on the parent map (see `grid::center`)
+    origin: (f32, f32),
+    parent_dimensions: (usize, usize),
+    // the hexes of the parent map the region may take its environments from
+    parents: HashMap<(usize, usize), Hex>,
+}
+
+impl Region {
+    pub fn new(
+        tiles: &[Vec<Hex>],
+        selection: &[(usize, usize)],
+        scale: u16,
+    ) -> Result<Region, String> {
+        let mut selection = selection.to_vec();
+        selection.sort();
+        selection.dedup();
+        let first = *selection.first().ok_or("no hexes are selected")?;
+        let parent_dimensions = (tiles[0].len(), tiles.len());
+        let width = parent_dimensions.0 as f32;
+
+        // the map wraps around, so hexes across its left/right edge are moved next to each other
+        let reference = grid::center(first).0;
+        let centers: Vec<(f32, f32)> = selection
+            .iter()
+            .map(|&index| {
+                let (x, y) = grid::center(index);
+                let x = [x - width, x, x + width]
+                    .into_iter()
+                    .min_by(|a, b| (a - reference).abs().total_cmp(&(b - reference).abs()))
+                    .unwrap_or(x);
+                (x, y)
+            })
+            .collect();
+        let min_x = centers.iter().map(|c| c.0).fold(f32::MAX, f32::min) - 0.5;
+        let max_x = centers.iter().map(|c| c.0).fold(f32::MIN, f32::max) + 0.5;
+        let min_y = centers.iter().map(|c| c.1).fold(f32::MAX, f32::min) - grid::CORNER_RADIUS;
+        let max_y = centers.iter().map(|c| c.1).fold(f32::MIN, f32::max) + grid::CORNER_RADIUS;
+
+        let scale = scale as f32;
+        // map dimensions must be even
+        let even = |n: f32| {
+            let n = n.ceil().max(1f32) as usize;
+            u16::try_from(n + n % 2)
+                .map_err(|_| String::from("the selected hexes are too far apart for a region"))
+        };
+        let dimensions = (
+            even((max_x - min_x) * scale)?,
+            even((max_y - min_y) * scale / grid::ROW_HEIGHT)?,
+        );
+        let origin = (min_x + 0.5 / scale, min_y + grid::CORNER_RADIUS / scale);
+
+        // the borders are shifted by less than a hex, so the hexes around the selection suffice
+        let rows = (min_y / grid::ROW_HEIGHT).floor() as i64 - 2
+            ..=(max_y / grid::ROW_HEIGHT).ceil() as i64 + 2;
+        let columns = min_x.floor() as i64 - 2..=max_x.ceil() as i64 + 2;
+        let mut parents = HashMap::new();
+        for y in rows {
+            for x in columns.clone() {
+                let (x, y) = wrap((x, y), parent_dimensions);
+                parents.insert((x, y), tiles[y][x]);
+            }
+        }
+
+        Ok(Region {
+            selection,
+            dimensions,
+            scale,
+            origin,
+            parent_dimensions,
+            parents,
+        })
+    }
+
+    // the hex of the parent map at the position (in hexes of the region, see `grid::center`)
+    fn parent(&self, position: (f32, f32)) -> Hex {
+        let position = (
+            self.origin.0 + position.0 / self.scale,
+            self.origin.1 + position.1 / self.scale,
+        );
+        let index = wrap(grid::hex_at(position), self.parent_dimensions);
+        self.parents.get(&index).copied().unwrap_or(Hex::EMPTY)
+    }
+}
+
+// the hex on the map, beyond its top/bottom the closest row is used
+fn wrap(index: (i64, i64), dimensions: (usize, usize)) -> (usize, usize) {
+    let x = index.0.rem_euclid(dimensions.0 as i64) as usize;
+    let y = index.1.clamp(0, dimensions.1 as i64 - 1) as usize;
+    (x, y)
+}
+
+// How a region has been generated, saved (see `RegionConfig::path`) so that entering the same
+// hexes of the same map again, even after a restart, generates the same region
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct RegionRecord {
+    // seed and size of the map the region is part of
+    pub parent_seed: u64,
+    pub parent_size: (u16, u16),
+    // the selected hexes of the parent map, sorted
+    pub selection: Vec<(usize, usize)>,
+    pub seed: u64,
+    pub scale: u16,
+}
+
+// the records of all regions that have been generated, none if the file doesn't exist yet
+pub fn load_records(path: &str) -> Result<Vec<RegionRecord>, String> {
+    match fs::read_to_string(path) {
+        Ok(content) => serde_json::from_str(&content)
+            .map_err(|e| format!("failed to read the regions {path}: {e}")),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(format!("failed to read the regions {path}: {e}")),
+    }
+}
+
+// adds the record to the file, replacing a previous record of the same hexes of the same map
+pub fn save_record(path: &str, record: &RegionRecord) -> Result<(), String> {
+    let mut records = load_records(path)?;
+    records.retain(|r| {
+        (r.parent_seed, r.parent_size, &r.selection)
+            != (record.parent_seed, record.parent_size, &record.selection)
+    });
+    records.push(record.clone());
+    let json = serde_json::to_string_pretty(&records).map_err(|e| e.to_string())?;
+    fs::write(path, json).map_err(|e| format!("failed to save the regions {path}: {e}"))
+}
+
+// Every hex of the region takes on the environment and climate of the parent hex it lies in. The
+// borders between the parent hexes are shifted randomly, so that the environments of the neighbours
+// of the selection reach into it and the borders of the region match the parent map
+pub struct RegionGenerator {
+    pub region: Arc<Region>,
+    // see `ProceduralGenerator::smoothing_strength`
+    pub smoothing_strength: u8,
+    pub config: Arc<GeneratorConfig>,
+}
+
+impl MapGenerator for RegionGenerator {
+    fn populate(
+        &self,
+        map: Arc<RwLock<Map>>,
+        dimensions: (u16, u16),
+        seed: u64,
+        playback: &Playback,
+    ) {
+        let width = dimensions.0 as usize;
+        let writer = ChunkWriter::new(&map, playback);
+        let config = &self.config.region;
+        // the variety within a single environment
+        let transformer = RandomGenerator {
+            config: self.config.clone(),
+        };
+        let jitter = config.jitter.min(100) as f32 / 100f32 * self.region.scale;
+
+        row_chunks(dimensions.1 as usize)
+            .into_par_iter()
+            .enumerate()
+            .for_each(|(i, rows)| {
+                let mut rng = chunk_rng(seed, 0, i);
+                for y in rows {
+                    let mut row = vec![Hex::EMPTY; width];
+                    for (x, hex) in row.iter_mut().enumerate() {
+                        let (c_x, c_y) = grid::center((x, y));
+                        let shift = (
+                            rng.gen_range(-1f32..=1f32) * jitter,
+                            rng.gen_range(-1f32..=1f32) * jitter,
+                        );
+                        let parent = self.region.parent((c_x + shift.0, c_y + shift.1));
+                        hex.environment = parent.environment;
+                        if rng.gen_range(0..100) < config.detail {
+                            hex.environment = transformer.transform_hex(&mut rng, hex.environment);
+                        }
+                        hex.temperature = parent.temperature;
+                        hex.precipitation = parent.precipitation;
+                        // the relief of the region is added on top (see `elevation::derive`)
+                        hex.elevation = parent.elevation.max(0);
+                        writer.hex_done(x, y, *hex);
+                    }
+                    writer.row_done(y, &row);
+                }
+            });
+    }
+
+    fn smooth(
+        &self,
+        map: Arc<RwLock<Map>>,
+        dimensions: (u16, u16),
+        seed: u64,
+        iteration: u16,
+        playback: &Playback,
+    ) {
+        smooth_strays(
+            &map,
+            dimensions,
+            seed,
+            iteration,
+            self.smoothing_strength,
+            false,
+            playback,
+        );
+    }
+}
+
+// Only hexes that most of their neighbours disagree with are smoothed (with the given chance in
+// percent), which removes stray hexes without moving the borders. Regions don't wrap around like
+// whole maps, so `wraps` is false for them
+#[allow(clippy::too_many_arguments)]
+pub(super) fn smooth_strays(
+    map: &Arc<RwLock<Map>>,
+    dimensions: (u16, u16),
+    seed: u64,
+    iteration: u16,
+    strength: u8,
+    wraps: bool,
+    playback: &Playback,
+) {
+    let tiles = match snapshot(map) {
+        Ok(t) => t,
+        Err(e) => {
+            eprintln!("Failed to smooth map: {e}");
+            return;
+        }
+    };
+    let writer = ChunkWriter::new(map, playback);
+    let dimensions = (dimensions.0 as usize, dimensions.1 as usize);
+
+    row_chunks(dimensions.1)
+        .into_par_iter()
+        .enumerate()
+        .for_each(|(i, rows)| {
+            let mut rng = chunk_rng(seed, iteration as u32 + 1, i);
+            for y in rows {
+                let mut row = tiles[y].clone();
+                for (x, hex) in row.iter_mut().enumerate() {
+                    if rng.gen_range(0..100) < strength {
+                        let mut counts: HashMap<Environment, usize> = HashMap::new();
+                        for (n_x, n_y) in grid::neighbours((x, y), dimensions) {
+                            if !wraps && n_x.abs_diff(x) > 1 {
+                                continue;
+                            }
+                            *counts.entry(tiles[n_y][n_x].environment).or_default() += 1;
+                        }
+                        let (environment, count) = counts
+                            .into_iter()
+                            .max_by_key(|&(env, count)| (count, env))
+                            .unwrap_or((hex.environment, 0));
+                        if count > 3 {
+                            hex.environment = environment;
+                        }
+                    }
+                    writer.hex_done(x, y, *hex);
+                }
+                writer.row_done(y, &row);
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::MapState;
+
+    #[test]
+    fn regions_are_smoothed_without_wrapping_around() {
+        let dimensions = (6, 6);
+        let mut map = MapState::create_empty_map(dimensions).unwrap();
+        for row in map.tiles.iter_mut() {
+            for (x, hex) in row.iter_mut().enumerate() {
+                hex.environment = if x == 0 {
+                    Environment::PLAINS
+                } else {
+                    Environment::FOREST
+                };
+            }
+        }
+        let map = Arc::new(RwLock::new(map));
+        smooth_strays(&map, dimensions, 1, 0, 100, false, &Playback::new());
+        let map = map.read().unwrap();
+        assert!(map
+            .tiles
+            .iter()
+            .all(|row| row[0].environment == Environment::PLAINS));
+    }
+
+    #[test]
+    fn records_of_the_same_hexes_are_replaced() {
+        let path = std::env::temp_dir().join(format!("regions_{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        let record = RegionRecord {
+            parent_seed: 7,
+            parent_size: (20, 10),
+            selection: vec![(1, 2), (2, 2)],
+            seed: 42,
+            scale: 10,
+        };
+        let other = RegionRecord {
+            selection: vec![(5, 5)],
+            ..record.clone()
+        };
+        let rescaled = RegionRecord {
+            scale: 4,
+            ..record.clone()
+        };
+
+        assert_eq!(load_records(path).unwrap(), Vec::new());
+        save_record(path, &record).unwrap();
+        save_record(path, &other).unwrap();
+        save_record(path, &rescaled).unwrap();
+        assert_eq!(load_records(path).unwrap(), vec![other, rescaled]);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,244 @@
+use crate::config::RoadConfig;
+use crate::map::chunks::snapshot;
+use crate::map::environments::Environment;
+use crate::map::settlements::{Settlement, SettlementSize};
+use crate::map::{grid, Hex, Map};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::sync::{Arc, RwLock};
+
+// cost of entering a hex of plains, the costs of all other hexes are relative to it
+const BASE_COST: u32 = 100;
+
+// Connects the settlements of the map with roads. Every settlement is connected to the closest
+// settlement of at least its size (cities to the two closest cities) via the cheapest route. Routes
+// are built one after another and following an existing road is cheap, so later routes tend to join
+// the roads that already exist instead of running right next to them
+pub fn build(
+    map: &Arc<RwLock<Map>>,
+    dimensions: (u16, u16),
+    config: &RoadConfig,
+) -> Result<(), String> {
+    let tiles = snapshot(map)?;
+    let settlements = map.read().map_err(|e| e.to_string())?.settlements.clone();
+    let dimensions = (dimensions.0 as usize, dimensions.1 as usize);
+    let costs = entry_costs(&tiles, dimensions, config);
+
+    let mut roads = vec![vec![0u8; dimensions.0]; dimensions.1];
+    let mut built = 0;
+    for (from, to) in routes(&settlements, dimensions, config) {
+        let (start, goal) = (settlements[from].position, settlements[to].position);
+        if let Some(path) = find_route(start, goal, &costs, &roads, dimensions, config) {
+            for step in path.windows(2) {
+                let direction = direction_between(step[0], step[1], dimensions);
+                let ((x1, y1), (x2, y2)) = (step[0], step[1]);
+                roads[y1][x1] |= 1 << direction;
+                roads[y2][x2] |= 1 << grid::opposite(direction);
+            }
+            built += 1;
+        }
+    }
+
+    let mut map = map.write().map_err(|e| e.to_string())?;
+    for (y, row) in roads.iter().enumerate() {
+        for (x, edges) in row.iter().enumerate() {
+            map.tiles[y][x].roads = *edges;
+        }
+    }
+    eprintln!("Built {built} roads");
+    Ok(())
+}
+
+// Hexploration travel time multiplier for moving from one hex to its neighbour in the given
+// direction, travelling along a road ignores the terrain. `None` if the move isn't possible on foot
+pub fn travel_cost(
+    map: &Map,
+    from: (usize, usize),
+    direction: u8,
+    dimensions: (usize, usize),
+) -> Option<u8> {
+    let (x, y) = grid::neighbour(from, direction, dimensions)?;
+    if map.tiles[from.1][from.0].roads & (1 << direction) != 0 {
+        return Some(1);
+    }
+    map.tiles[y][x].environment.movement_cost()
+}
+
+// pairs of settlements (indices) that should be connected, the most important routes first
+fn routes(
+    settlements: &[Settlement],
+    dimensions: (usize, usize),
+    config: &RoadConfig,
+) -> Vec<(usize, usize)> {
+    let mut routes = Vec::new();
+    for (i, settlement) in settlements.iter().enumerate() {
+        let mut candidates: Vec<(usize, usize)> = settlements
+            .iter()
+            .enumerate()
+            .filter(|&(j, other)| j != i && other.size >= settlement.size)
+            .map(|(j, other)| {
+                let distance = grid::distance(settlement.position, other.position, dimensions);
+                (distance, j)
+            })
+            .filter(|&(distance, _)| distance <= config.max_route_length as usize)
+            .collect();
+        candidates.sort();
+
+        let connections = if settlement.size == SettlementSize::City {
+            2
+        } else {
+            1
+        };
+        for &(_, j) in candidates.iter().take(connections) {
+            let route = (i.min(j), i.max(j));
+            if !routes.contains(&route) {
+                routes.push(route);
+            }
+        }
+    }
+    routes
+}
+
+// cost of entering each hex (without roads), `None` for hexes that cannot be entered
+fn entry_costs(
+    tiles: &[Vec<Hex>],
+    dimensions: (usize, usize),
+    config: &RoadConfig,
+) -> Vec<Vec<Option<u32>>> {
+    let environments = Environment::all();
+    let is_water: Vec<bool> = environments.iter().map(|e| e.is_water()).collect();
+    let movement_costs: Vec<Option<u8>> = environments.iter().map(|e| e.movement_cost()).collect();
+    let water = |hex: &Hex| is_water[hex.environment.id() as usize];
+
+    // distance of water hexes to the closest land hex
+    let (width, height) = dimensions;
+    let mut shore_distance = vec![vec![u16::MAX; width]; height];
+    let mut queue = VecDeque::new();
+    for (y, row) in tiles.iter().enumerate() {
+        for (x, hex) in row.iter().enumerate() {
+            if !water(hex) {
+                shore_distance[y][x] = 0;
+                queue.push_back((x, y));
+            }
+        }
+    }
+    while let Some((x, y)) = queue.pop_front() {
+        let distance = shore_distance[y][x] + 1;
+        if distance > config.max_crossing_distance {
+            continue;
+        }
+        for (nx, ny) in grid::neighbours((x, y), dimensions) {
+            if shore_distance[ny][nx] == u16::MAX {
+                shore_distance[ny][nx] = distance;
+                queue.push_back((nx, ny));
+            }
+        }
+    }
+
+    tiles
+        .iter()
+        .enumerate()
+        .map(|(y, row)| {
+            row.iter()
+                .enumerate()
+                .map(|(x, hex)| {
+                    if water(hex) {
+                        (shore_distance[y][x] <= config.max_crossing_distance)
+                            .then_some(config.crossing_cost)
+                    } else {
+                        movement_costs[hex.environment.id() as usize]
+                            .map(|cost| cost as u32 * BASE_COST)
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// A* search for the cheapest route, gives up once routes would become longer than the allowed detour
+fn find_route(
+    start: (usize, usize),
+    goal: (usize, usize),
+    costs: &[Vec<Option<u32>>],
+    roads: &[Vec<u8>],
+    dimensions: (usize, usize),
+    config: &RoadConfig,
+) -> Option<Vec<(usize, usize)>> {
+    // the estimate must never be larger than the actual cost, so it assumes a road all the way
+    let min_step_cost = config.road_cost.min(BASE_COST);
+    let estimate = |index| grid::distance(index, goal, dimensions) as u32 * min_step_cost;
+    let max_cost =
+        grid::distance(start, goal, dimensions) as u32 * BASE_COST * config.max_detour as u32;
+
+    let mut cheapest: HashMap<(usize, usize), u32> = HashMap::from([(start, 0)]);
+    let mut previous: HashMap<(usize, usize), (usize, usize)> = HashMap::new();
+    // ties are broken by the index to stay deterministic
+    let mut open = BinaryHeap::from([Reverse((estimate(start), start))]);
+
+    while let Some(Reverse((estimated_cost, current))) = open.pop() {
+        let cost = cheapest[&current];
+        if estimated_cost > cost + estimate(current) {
+            // a cheaper route to this hex has been found after it was queued
+            continue;
+        }
+        if current == goal {
+            let mut path = vec![goal];
+            while let Some(&before) = previous.get(path.last()?) {
+                path.push(before);
+            }
+            path.reverse();
+            return Some(path);
+        }
+
+        for direction in grid::DIRECTIONS {
+            let next = match grid::neighbour(current, direction, dimensions) {
+                Some(n) => n,
+                None => continue,
+            };
+            let step_cost = if roads[current.1][current.0] & (1 << direction) != 0 {
+                config.road_cost
+            } else {
+                match costs[next.1][next.0] {
+                    Some(c) => c,
+                    None => continue,
+                }
+            };
+            let next_cost = cost + step_cost;
+            if next_cost + estimate(next) > max_cost {
+                continue;
+            }
+            if !matches!(cheapest.get(&next), Some(&c) if c <= next_cost) {
+                cheapest.insert(next, next_cost);
+                previous.insert(next, current);
+                open.push(Reverse((next_cost + estimate(next), next)));
+            }
+        }
+    }
+    None
+}
+
+fn direction_between(from: (usize, usize), to: (usize, usize), dimensions: (usize, usize)) -> u8 {
+    grid::DIRECTIONS
+        .into_iter()
+        .find(|&d| grid::neighbour(from, d, dimensions) == Some(to))
+        .expect("consecutive hexes of a route must be neighbours")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::MapState;
+
+    #[test]
+    fn roads_ignore_the_terrain() {
+        let dimensions = (4, 4);
+        let mut map = MapState::create_empty_map((4, 4)).unwrap();
+        for hex in map.tiles.iter_mut().flatten() {
+            hex.environment = Environment::FOREST;
+        }
+        assert_eq!(travel_cost(&map, (1, 1), 0, dimensions), Some(2));
+        map.tiles[1][1].roads = 1;
+        assert_eq!(travel_cost(&map, (1, 1), 0, dimensions), Some(1));
+        assert_eq!(travel_cost(&map, (1, 1), 1, dimensions), Some(2));
+    }
+}
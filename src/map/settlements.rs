@@ -0,0 +1,198 @@
+use crate::config::SettlementConfig;
+use crate::map::chunks::{chunk_rng, row_chunks, snapshot, SETTLEMENT_PHASE};
+use crate::map::environments::Environment;
+use crate::map::{grid, Map};
+use rand::Rng;
+use rayon::prelude::*;
+use std::sync::{Arc, RwLock};
+
+// ordered from smallest to largest
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug)]
+pub enum SettlementSize {
+    Village,
+    Town,
+    City,
+}
+
+impl SettlementSize {
+    // roughly following the example settlements of the Gamemastery Guide
+    fn population_range(self) -> (u32, u32) {
+        match self {
+            SettlementSize::Village => (50, 400),
+            SettlementSize::Town => (400, 5_000),
+            SettlementSize::City => (5_000, 25_000),
+        }
+    }
+
+    // PF2e settlement levels of the smallest/largest settlements of this size
+    fn level_range(self) -> (u8, u8) {
+        match self {
+            SettlementSize::Village => (0, 2),
+            SettlementSize::Town => (2, 6),
+            SettlementSize::City => (5, 10),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Settlement {
+    pub position: (usize, usize),
+    pub size: SettlementSize,
+    // PF2e settlement level
+    pub level: u8,
+    pub population: u32,
+    // see `names::name`
+    pub name: Option<String>,
+}
+
+impl Settlement {
+    fn new<R: Rng>(position: (usize, usize), size: SettlementSize, rng: &mut R) -> Settlement {
+        let (min_population, max_population) = size.population_range();
+        let (min_level, max_level) = size.level_range();
+        let population = rng.gen_range(min_population..=max_population);
+        // larger settlements of the same size tend to have a higher level
+        let level = min_level as u32
+            + (population - min_population) * (max_level - min_level) as u32
+                / (max_population - min_population);
+
+        Settlement {
+            position,
+            size,
+            level: level as u8,
+            population,
+            name: None,
+        }
+    }
+}
+
+// Places settlements on the finished terrain. Every suitable hex gets a random priority that is
+// weighted by how attractive the hex is, the hexes are then settled in the order of their priority
+// (cities first) as long as they keep the configured distance to all previously placed settlements.
+// The hexes of cities become urban if `urban` is true
+pub fn place(
+    map: &Arc<RwLock<Map>>,
+    dimensions: (u16, u16),
+    seed: u64,
+    config: &SettlementConfig,
+    urban: bool,
+) -> Result<(), String> {
+    let tiles = snapshot(map)?;
+    let (width, height) = (dimensions.0 as usize, dimensions.1 as usize);
+
+    // looking the environments up once instead of for every hex and its neighbours
+    let environments = Environment::all();
+    let is_water: Vec<bool> = environments.iter().map(|e| e.is_water()).collect();
+    let weights: Vec<u32> = environments
+        .iter()
+        .map(|e| config.environment_weights.get(e).copied().unwrap_or(0))
+        .collect();
+
+    let mut candidates: Vec<(f64, usize, usize)> = row_chunks(height)
+        .into_par_iter()
+        .enumerate()
+        .flat_map_iter(|(chunk, rows)| {
+            let mut rng = chunk_rng(seed, SETTLEMENT_PHASE, chunk);
+            let mut chunk_candidates = Vec::new();
+            for y in rows {
+                for x in 0..width {
+                    let weight = weights[tiles[y][x].environment.id() as usize];
+                    if weight == 0 {
+                        continue;
+                    }
+                    let neighbours = grid::neighbours((x, y), (width, height));
+                    let environment = |&(nx, ny): &(usize, usize)| tiles[ny][nx].environment;
+                    let mut weight = weight;
+                    if neighbours
+                        .iter()
+                        .any(|n| is_water[environment(n).id() as usize])
+                    {
+                        weight += config.coast_bonus;
+                    }
+                    if neighbours
+                        .iter()
+                        .any(|n| environment(n) == Environment::PLAINS)
+                    {
+                        weight += config.plains_bonus;
+                    }
+                    if tiles[y][x].rivers != 0 {
+                        weight += config.river_bonus;
+                    }
+                    // weighted random order (Efraimidis-Spirakis)
+                    let priority = rng.gen::<f64>().powf(1.0 / weight as f64);
+                    chunk_candidates.push((priority, x, y));
+                }
+            }
+            chunk_candidates
+        })
+        .collect();
+    candidates.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    let land = tiles
+        .iter()
+        .flatten()
+        .filter(|hex| {
+            hex.environment != Environment::NONE && !is_water[hex.environment.id() as usize]
+        })
+        .count() as u32;
+    let sizes = [
+        (
+            SettlementSize::City,
+            land / config.land_per_city,
+            config.city_spacing,
+        ),
+        (
+            SettlementSize::Town,
+            land / config.land_per_town,
+            config.town_spacing,
+        ),
+        (
+            SettlementSize::Village,
+            land / config.land_per_village,
+            config.village_spacing,
+        ),
+    ];
+    let max_spacing = sizes
+        .iter()
+        .map(|(_, _, spacing)| *spacing)
+        .max()
+        .unwrap_or(0);
+
+    // distance to the closest settlement, only tracked up to the largest spacing
+    let mut closest = vec![vec![u16::MAX; width]; height];
+    let mut rng = chunk_rng(seed, SETTLEMENT_PHASE, row_chunks(height).len());
+    let mut settlements = Vec::new();
+    for (size, amount, spacing) in sizes {
+        let mut placed = 0;
+        for &(_, x, y) in &candidates {
+            if placed == amount {
+                break;
+            }
+            if closest[y][x] < spacing.max(1) {
+                continue;
+            }
+            for ((nx, ny), distance) in grid::within_distance((x, y), max_spacing, (width, height))
+            {
+                closest[ny][nx] = closest[ny][nx].min(distance);
+            }
+            settlements.push(Settlement::new((x, y), size, &mut rng));
+            placed += 1;
+        }
+    }
+
+    let mut map = map.write().map_err(|e| e.to_string())?;
+    for settlement in &settlements {
+        if urban && settlement.size == SettlementSize::City {
+            let (x, y) = settlement.position;
+            map.tiles[y][x].environment = Environment::URBAN;
+        }
+    }
+    let count = |size| settlements.iter().filter(|s| s.size == size).count();
+    eprintln!(
+        "Placed {} cities, {} towns and {} villages",
+        count(SettlementSize::City),
+        count(SettlementSize::Town),
+        count(SettlementSize::Village),
+    );
+    map.settlements = settlements;
+    Ok(())
+}
@@ -0,0 +1,154 @@
+use crate::config::{GeneratorConfig, SketchConfig};
+use crate::map::chunks::{chunk_rng, row_chunks, ChunkWriter};
+use crate::map::environments::Environment;
+use crate::map::grid;
+use crate::map::playback::Playback;
+use crate::map::region_gen::smooth_strays;
+use crate::map::{Hex, Map, MapGenerator};
+use rand::Rng;
+use rayon::prelude::*;
+use std::fs;
+use std::sync::{Arc, RwLock};
+
+// the environments of the sketch (see `SketchConfig`), row by row
+pub fn load_sketch(config: &SketchConfig) -> Result<Vec<Vec<Environment>>, String> {
+    let text = fs::read_to_string(&config.path)
+        .map_err(|e| format!("failed to read the sketch '{}': {e}", config.path))?;
+    let mut rows = Vec::new();
+    for (line, text) in text.lines().enumerate() {
+        let text = text.trim_end();
+        if text.is_empty() {
+            continue;
+        }
+        let row = text
+            .chars()
+            .enumerate()
+            .map(|(column, c)| {
+                config.legend.get(&c).copied().ok_or_else(|| {
+                    format!(
+                        "unknown character '{c}' in line {} column {} of the sketch",
+                        line + 1,
+                        column + 1
+                    )
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        rows.push(row);
+    }
+    match rows.first() {
+        None => Err(String::from("the sketch is empty")),
+        Some(first) if rows.iter().any(|r| r.len() != first.len()) => {
+            Err(String::from("all rows of the sketch must be equally long"))
+        }
+        Some(_) => Ok(rows),
+    }
+}
+
+// Subdivides the sketch into grids with twice as many rows and columns until it reaches the size of
+// the map. Every hex takes on the environment of the hex of the coarser grid it lies in, with the
+// borders between them shifted randomly, so that the coastlines get more ragged with every level
+pub struct SketchGenerator {
+    pub sketch: Vec<Vec<Environment>>,
+    // see `ProceduralGenerator::smoothing_strength`
+    pub smoothing_strength: u8,
+    pub config: Arc<GeneratorConfig>,
+}
+
+impl MapGenerator for SketchGenerator {
+    fn populate(
+        &self,
+        map: Arc<RwLock<Map>>,
+        dimensions: (u16, u16),
+        seed: u64,
+        playback: &Playback,
+    ) {
+        let dimensions = (dimensions.0 as usize, dimensions.1 as usize);
+        let writer = ChunkWriter::new(&map, playback);
+        let jitter = self.config.sketch.jitter.min(100) as f32 / 100f32;
+
+        let mut grid = self.sketch.clone();
+        let mut level = 0;
+        loop {
+            let current = (grid[0].len(), grid.len());
+            let next = (
+                (current.0 * 2).min(dimensions.0),
+                (current.1 * 2).min(dimensions.1),
+            );
+            // only the last level is written to the map
+            let last = next == dimensions;
+            grid = subdivide(&grid, next, jitter, seed, level, last.then_some(&writer));
+            if last {
+                break;
+            }
+            level += 1;
+        }
+    }
+
+    fn smooth(
+        &self,
+        map: Arc<RwLock<Map>>,
+        dimensions: (u16, u16),
+        seed: u64,
+        iteration: u16,
+        playback: &Playback,
+    ) {
+        smooth_strays(
+            &map,
+            dimensions,
+            seed,
+            iteration,
+            self.smoothing_strength,
+            true,
+            playback,
+        );
+    }
+}
+
+fn subdivide(
+    coarse: &[Vec<Environment>],
+    dimensions: (usize, usize),
+    // in hexes of the coarse grid
+    jitter: f32,
+    seed: u64,
+    level: usize,
+    writer: Option<&ChunkWriter>,
+) -> Vec<Vec<Environment>> {
+    let (width, height) = dimensions;
+    let (coarse_width, coarse_height) = (coarse[0].len(), coarse.len());
+
+    row_chunks(height)
+        .into_par_iter()
+        .enumerate()
+        .flat_map_iter(|(i, rows)| {
+            // every level uses its own streams (there are far less than 2^16 chunks per level)
+            let mut rng = chunk_rng(seed, 0, level << 16 | i);
+            let mut chunk_rows = Vec::with_capacity(rows.len());
+            for y in rows {
+                let mut row = vec![Hex::EMPTY; width];
+                for (x, hex) in row.iter_mut().enumerate() {
+                    // the same relative position on the coarse grid (see `grid::center`)
+                    let (c_x, _) = grid::center((x, y));
+                    let coarse_x = (c_x + 0.5) / width as f32 * coarse_width as f32 - 0.5;
+                    let coarse_row = (y as f32 + 0.5) / height as f32 * coarse_height as f32 - 0.5;
+                    let position = (
+                        coarse_x + rng.gen_range(-1f32..=1f32) * jitter,
+                        coarse_row * grid::ROW_HEIGHT + rng.gen_range(-1f32..=1f32) * jitter,
+                    );
+                    // the map wraps around horizontally but not vertically
+                    let (coarse_x, coarse_y) = grid::hex_at(position);
+                    let coarse_x = coarse_x.rem_euclid(coarse_width as i64) as usize;
+                    let coarse_y = coarse_y.clamp(0, coarse_height as i64 - 1) as usize;
+                    hex.environment = coarse[coarse_y][coarse_x];
+                    if let Some(writer) = writer {
+                        writer.hex_done(x, y, *hex);
+                    }
+                }
+                if let Some(writer) = writer {
+                    writer.row_done(y, &row);
+                }
+                chunk_rows.push(row.iter().map(|hex| hex.environment).collect());
+            }
+            chunk_rows
+        })
+        .collect()
+}
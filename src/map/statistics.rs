@@ -0,0 +1,254 @@
+use crate::map::areas::{find_areas, AreaKind};
+use crate::map::climate::latitude;
+use crate::map::environments::Environment;
+use crate::map::{grid, Map};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+
+// width of the latitude bands of the report in degrees
+const BAND_DEGREES: f32 = 30f32;
+
+// Counts of the environments and the shape of the land of a map, e.g. to compare the effects of
+// different chances. Landmasses and regions are the areas of the map (see `Areas`)
+#[derive(Serialize, Debug)]
+pub struct MapStatistics {
+    pub hexes: usize,
+    pub land_hexes: usize,
+    pub land_percent: f32,
+    // contiguous land
+    pub landmasses: usize,
+    pub largest_landmass: usize,
+    // edges between land and water hexes
+    pub coastline: usize,
+    // ordered by id
+    pub environments: Vec<EnvironmentStatistics>,
+    // from north to south
+    pub latitude_bands: Vec<BandStatistics>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct EnvironmentStatistics {
+    pub environment: String,
+    pub hexes: usize,
+    pub percent: f32,
+    // contiguous hexes of this environment
+    pub regions: usize,
+    pub largest_region: usize,
+    pub mean_region: f32,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BandStatistics {
+    // in degrees, positive in the northern half of the map
+    pub from_latitude: f32,
+    pub to_latitude: f32,
+    pub hexes: usize,
+    pub land_percent: f32,
+    // percentages of the environments found in the band
+    pub environments: BTreeMap<String, f32>,
+}
+
+impl Map {
+    // see `MapStatistics`
+    pub fn statistics(&self) -> MapStatistics {
+        let height = self.tiles.len();
+        let width = self.tiles.first().map_or(0, |row| row.len());
+        let dimensions = (width, height);
+        let hexes = width * height;
+        let percent = |count: usize, total: usize| {
+            if total == 0 {
+                0f32
+            } else {
+                count as f32 * 100f32 / total as f32
+            }
+        };
+
+        let mut counts: BTreeMap<Environment, usize> = BTreeMap::new();
+        let mut land_hexes = 0;
+        let mut coastline = 0;
+        for (y, row) in self.tiles.iter().enumerate() {
+            for (x, hex) in row.iter().enumerate() {
+                *counts.entry(hex.environment).or_default() += 1;
+                if !hex.environment.is_water() {
+                    land_hexes += 1;
+                    coastline += grid::neighbours((x, y), dimensions)
+                        .into_iter()
+                        .filter(|&(n_x, n_y)| self.tiles[n_y][n_x].environment.is_water())
+                        .count();
+                }
+            }
+        }
+
+        // maps that are still being generated have no areas yet, only landmasses and environment
+        // areas are counted, which don't depend on the size of lakes
+        let detected;
+        let areas = if self.areas.areas.is_empty() {
+            detected = find_areas(&self.tiles, 0);
+            &detected
+        } else {
+            &self.areas
+        };
+        let land_sizes: Vec<usize> = areas
+            .of_kind(AreaKind::Landmass)
+            .map(|area| area.size)
+            .collect();
+        let mut regions: BTreeMap<Environment, Vec<usize>> = BTreeMap::new();
+        for area in &areas.areas {
+            if let AreaKind::Environment(environment) = area.kind {
+                regions.entry(environment).or_default().push(area.size);
+            }
+        }
+
+        let environments = counts
+            .into_iter()
+            .map(|(environment, count)| {
+                let sizes = regions.remove(&environment).unwrap_or_default();
+                EnvironmentStatistics {
+                    environment: environment.key(),
+                    hexes: count,
+                    percent: percent(count, hexes),
+                    regions: sizes.len(),
+                    largest_region: sizes.iter().copied().max().unwrap_or(0),
+                    mean_region: count as f32 / sizes.len().max(1) as f32,
+                }
+            })
+            .collect();
+
+        // the starting latitude, hexes, land hexes and hexes of every environment of each band
+        let mut bands: Vec<(f32, usize, usize, BTreeMap<String, usize>)> = Vec::new();
+        let max_y = height.saturating_sub(1);
+        for (y, row) in self.tiles.iter().enumerate() {
+            // bands start at the north pole, the south pole belongs to the last one
+            let band_count = (180f32 / BAND_DEGREES) as usize;
+            let band = ((90f32 - latitude(y, max_y)) / BAND_DEGREES) as usize;
+            let from_latitude = 90f32 - band.min(band_count - 1) as f32 * BAND_DEGREES;
+            if bands.last().map(|b| b.0) != Some(from_latitude) {
+                bands.push((from_latitude, 0, 0, BTreeMap::new()));
+            }
+            if let Some((_, hexes, land, environments)) = bands.last_mut() {
+                *hexes += row.len();
+                for hex in row {
+                    if !hex.environment.is_water() {
+                        *land += 1;
+                    }
+                    *environments.entry(hex.environment.key()).or_default() += 1;
+                }
+            }
+        }
+        let latitude_bands = bands
+            .into_iter()
+            .map(
+                |(from_latitude, hexes, land, environments)| BandStatistics {
+                    from_latitude,
+                    to_latitude: (from_latitude - BAND_DEGREES).max(-90f32),
+                    hexes,
+                    land_percent: percent(land, hexes),
+                    environments: environments
+                        .into_iter()
+                        .map(|(key, count)| (key, percent(count, hexes)))
+                        .collect(),
+                },
+            )
+            .collect();
+
+        MapStatistics {
+            hexes,
+            land_hexes,
+            land_percent: percent(land_hexes, hexes),
+            landmasses: land_sizes.len(),
+            largest_landmass: land_sizes.iter().copied().max().unwrap_or(0),
+            coastline,
+            environments,
+            latitude_bands,
+        }
+    }
+}
+
+impl fmt::Display for MapStatistics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} hexes, {} of them land ({:.1}%)",
+            self.hexes, self.land_hexes, self.land_percent
+        )?;
+        writeln!(
+            f,
+            "{} landmasses, the largest with {} hexes, {} hexes of coastline",
+            self.landmasses, self.largest_landmass, self.coastline
+        )?;
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{:<16} {:>9} {:>7} {:>8} {:>8} {:>8}",
+            "environment", "hexes", "%", "regions", "largest", "mean"
+        )?;
+        for environment in &self.environments {
+            writeln!(
+                f,
+                "{:<16} {:>9} {:>7.2} {:>8} {:>8} {:>8.1}",
+                environment.environment,
+                environment.hexes,
+                environment.percent,
+                environment.regions,
+                environment.largest_region,
+                environment.mean_region
+            )?;
+        }
+        writeln!(f)?;
+        for band in &self.latitude_bands {
+            // the most common environments of the band
+            let mut shares: Vec<(&String, &f32)> = band.environments.iter().collect();
+            shares.sort_by(|a, b| b.1.total_cmp(a.1).then(a.0.cmp(b.0)));
+            let shares: Vec<String> = shares
+                .iter()
+                .take(4)
+                .map(|(environment, share)| format!("{environment} {share:.1}%"))
+                .collect();
+            writeln!(
+                f,
+                "{:>4.0}° to {:>4.0}°: {:>5.1}% land, {}",
+                band.from_latitude,
+                band.to_latitude,
+                band.land_percent,
+                shares.join(", ")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::areas::Areas;
+    use crate::map::Hex;
+
+    #[test]
+    fn latitude_bands_are_counted_in_percent() {
+        let water = Hex {
+            environment: Environment::AQUATIC,
+            ..Hex::EMPTY
+        };
+        let mut tiles = vec![vec![water; 4]; 6];
+        tiles[2][1].environment = Environment::PLAINS;
+        let map = Map {
+            tiles,
+            settlements: Vec::new(),
+            entrances: Vec::new(),
+            areas: Areas::default(),
+            labels: Vec::new(),
+        };
+
+        let statistics = map.statistics();
+        assert_eq!(statistics.latitude_bands.len(), 6);
+        let band = &statistics.latitude_bands[2];
+        assert_eq!((band.from_latitude, band.hexes), (30f32, 4));
+        assert_eq!(band.land_percent, 25f32);
+        assert_eq!(band.environments["plains"], 25f32);
+        assert_eq!(band.environments["aquatic"], 75f32);
+        assert_eq!(statistics.latitude_bands[3].land_percent, 0f32);
+        // the areas are detected if the map has none yet
+        assert_eq!(statistics.landmasses, 1);
+    }
+}
@@ -0,0 +1,193 @@
+use crate::config::TectonicsConfig;
+use crate::map::chunks::{chunk_rng, TECTONICS_PHASE};
+use crate::map::grid;
+use rand::Rng;
+use rayon::prelude::*;
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+
+// Simplified plate tectonics: the map is split into plates (the hexes closest to randomly placed
+// centers) which drift in random directions. Where two plates push against each other, mountains and
+// volcanic arcs rise; where they drift apart, rifts open up. The effect fades with the distance to the
+// boundary
+pub struct Tectonics {
+    // -100 (plates drifting apart at full speed) to 100 (plates colliding at full speed), 0 far away
+    // from any boundary
+    stress: Vec<Vec<i8>>,
+    // distance to the closest plate boundary, only tracked within the configured boundary width
+    boundary_distance: Vec<Vec<u16>>,
+}
+
+struct Plate {
+    center: (usize, usize),
+    // drift per hex and step, at most 1
+    motion: (f32, f32),
+}
+
+impl Tectonics {
+    pub fn simulate(dimensions: (usize, usize), seed: u64, config: &TectonicsConfig) -> Tectonics {
+        let (width, height) = dimensions;
+        let mut rng = chunk_rng(seed, TECTONICS_PHASE, 0);
+        let plates: Vec<Plate> = (0..config.plates.max(1))
+            .map(|_| {
+                let angle = rng.gen_range(0f32..2f32 * PI);
+                let speed = rng.gen_range(0.2f32..=1f32);
+                Plate {
+                    center: (rng.gen_range(0..width), rng.gen_range(0..height)),
+                    motion: (angle.cos() * speed, angle.sin() * speed),
+                }
+            })
+            .collect();
+
+        let plate_ids: Vec<Vec<usize>> = (0..height)
+            .into_par_iter()
+            .map(|y| {
+                (0..width)
+                    .map(|x| {
+                        (0..plates.len())
+                            .min_by_key(|&i| grid::distance((x, y), plates[i].center, dimensions))
+                            .unwrap_or(0)
+                    })
+                    .collect()
+            })
+            .collect();
+
+        // stress right at the boundaries, based on how fast the plates move towards each other
+        let boundary_stress: Vec<Vec<Option<i8>>> = (0..height)
+            .into_par_iter()
+            .map(|y| {
+                (0..width)
+                    .map(|x| {
+                        let plate = &plates[plate_ids[y][x]];
+                        let (mut total, mut count) = (0f32, 0);
+                        for direction in grid::DIRECTIONS {
+                            let (n_x, n_y) = match grid::neighbour((x, y), direction, dimensions) {
+                                Some(n) => n,
+                                None => continue,
+                            };
+                            if plate_ids[n_y][n_x] == plate_ids[y][x] {
+                                continue;
+                            }
+                            let other = &plates[plate_ids[n_y][n_x]];
+                            let (dx, dy) = direction_vector(direction);
+                            let relative = (
+                                plate.motion.0 - other.motion.0,
+                                plate.motion.1 - other.motion.1,
+                            );
+                            total += relative.0 * dx + relative.1 * dy;
+                            count += 1;
+                        }
+                        // the relative speed can be up to 2, but plates rarely meet head-on
+                        (count > 0)
+                            .then(|| (total / count as f32 * 100f32).clamp(-100f32, 100f32) as i8)
+                    })
+                    .collect()
+            })
+            .collect();
+
+        // every hex close to a boundary takes on the stress of the closest boundary hex, weakened by
+        // the distance to it
+        let mut stress = vec![vec![0i8; width]; height];
+        let mut boundary_distance = vec![vec![u16::MAX; width]; height];
+        let mut queue = VecDeque::new();
+        for (y, row) in boundary_stress.iter().enumerate() {
+            for (x, value) in row.iter().enumerate() {
+                if let Some(value) = value {
+                    stress[y][x] = *value;
+                    boundary_distance[y][x] = 0;
+                    queue.push_back((x, y));
+                }
+            }
+        }
+        while let Some((x, y)) = queue.pop_front() {
+            let distance = boundary_distance[y][x] + 1;
+            if distance > config.boundary_width {
+                continue;
+            }
+            for (n_x, n_y) in grid::neighbours((x, y), dimensions) {
+                if boundary_distance[n_y][n_x] == u16::MAX {
+                    boundary_distance[n_y][n_x] = distance;
+                    stress[n_y][n_x] = stress[y][x];
+                    queue.push_back((n_x, n_y));
+                }
+            }
+        }
+        let falloff = config.boundary_width as i32 + 1;
+        for (y, row) in stress.iter_mut().enumerate() {
+            for (x, value) in row.iter_mut().enumerate() {
+                let distance = (boundary_distance[y][x] as i32).min(falloff);
+                *value = (*value as i32 * (falloff - distance) / falloff) as i8;
+            }
+        }
+
+        Tectonics {
+            stress,
+            boundary_distance,
+        }
+    }
+
+    pub fn influence(&self, index: (usize, usize)) -> Influence {
+        let (x, y) = index;
+        Influence {
+            stress: self.stress[y][x] as i32,
+            boundary_distance: self.boundary_distance[y][x],
+        }
+    }
+}
+
+// how the plates affect a single hex
+#[derive(Copy, Clone)]
+pub struct Influence {
+    // see `Tectonics::stress`
+    pub stress: i32,
+    pub boundary_distance: u16,
+}
+
+impl Influence {
+    // height in metres the hex is raised (or lowered, in rifts) by
+    pub fn uplift(self, config: &TectonicsConfig) -> i16 {
+        let uplift = if self.stress >= 0 {
+            self.stress * config.max_uplift as i32 / 100
+        } else {
+            self.stress * config.max_rift_depth as i32 / 100
+        };
+        uplift.clamp(i16::MIN as i32, i16::MAX as i32) as i16
+    }
+}
+
+// points from the middle of a hex to the middle of its neighbour in the given direction
+fn direction_vector(direction: u8) -> (f32, f32) {
+    let diagonal = 3f32.sqrt() / 2f32;
+    match direction {
+        grid::TOP_LEFT => (-0.5, -diagonal),
+        grid::TOP_RIGHT => (0.5, -diagonal),
+        grid::RIGHT => (1.0, 0.0),
+        grid::BOTTOM_RIGHT => (0.5, diagonal),
+        grid::BOTTOM_LEFT => (-0.5, diagonal),
+        _ => (-1.0, 0.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::GeneratorConfig;
+
+    #[test]
+    fn uplift_is_bounded_by_the_height_range() {
+        // missing sections are taken from the default config
+        let mut config = GeneratorConfig::parse("").unwrap().0.procedural.tectonics;
+        config.max_uplift = u16::MAX;
+        config.max_rift_depth = u16::MAX;
+        let collision = Influence {
+            stress: i8::MAX as i32,
+            boundary_distance: 0,
+        };
+        let rift = Influence {
+            stress: i8::MIN as i32,
+            ..collision
+        };
+        assert_eq!(collision.uplift(&config), i16::MAX);
+        assert_eq!(rift.uplift(&config), i16::MIN);
+    }
+}
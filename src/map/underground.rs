@@ -0,0 +1,140 @@
+use crate::config::UndergroundConfig;
+use crate::map::chunks::{chunk_rng, row_chunks, snapshot, ENTRANCE_PHASE, UNDERGROUND_PHASE};
+use crate::map::environments::Environment;
+use crate::map::playback::{Playback, PlaybackGranularity};
+use crate::map::{grid, Hex, Map};
+use rand::Rng;
+use rayon::prelude::*;
+use std::sync::{Arc, RwLock};
+
+// Generates the underground layer beneath the finished surface. Every hex starts out as either solid
+// rock or a cavern (more likely beneath mountains, flooded beneath lakes), the smoothing then turns
+// the noise into connected cave systems. Some of the caverns below land are connected to the surface
+// by entrances, which are stored on both layers
+pub fn generate(
+    surface: &Arc<RwLock<Map>>,
+    underground: &Arc<RwLock<Map>>,
+    dimensions: (u16, u16),
+    seed: u64,
+    config: &UndergroundConfig,
+    playback: &Playback,
+) -> Result<(), String> {
+    let surface_tiles = snapshot(surface)?;
+    let dimensions = (dimensions.0 as usize, dimensions.1 as usize);
+    let (width, height) = dimensions;
+    let environments = Environment::all();
+    let is_water: Vec<bool> = environments.iter().map(|e| e.is_water()).collect();
+
+    let mut tiles: Vec<Vec<Hex>> = row_chunks(height)
+        .into_par_iter()
+        .enumerate()
+        .flat_map_iter(|(chunk, rows)| {
+            let mut rng = chunk_rng(seed, UNDERGROUND_PHASE, chunk);
+            let mut chunk_rows = Vec::with_capacity(rows.len());
+            for y in rows {
+                let mut row = vec![Hex::EMPTY; width];
+                for (x, hex) in row.iter_mut().enumerate() {
+                    let above = surface_tiles[y][x].environment;
+                    // the sea has been divided into zones already, only lakes remain aquatic
+                    let (open, chance) = if above == Environment::AQUATIC {
+                        (Environment::FLOODED_CAVE, config.flooded_chance_under_lakes)
+                    } else if above == Environment::MOUNTAIN {
+                        (Environment::CAVERN, config.cavern_chance_under_mountains)
+                    } else {
+                        (Environment::CAVERN, config.cavern_chance)
+                    };
+                    hex.environment = if rng.gen_ratio(chance.min(100), 100) {
+                        open
+                    } else {
+                        Environment::ROCK
+                    };
+                }
+                chunk_rows.push(row);
+            }
+            chunk_rows
+        })
+        .collect();
+    underground.write().map_err(|e| e.to_string())?.tiles = tiles.clone();
+    playback.checkpoint(PlaybackGranularity::Phase);
+
+    for _ in 0..config.smoothing_iterations {
+        tiles = (0..height)
+            .into_par_iter()
+            .map(|y| {
+                (0..width)
+                    .map(|x| Hex {
+                        environment: smoothed_environment(&tiles, (x, y), dimensions),
+                        ..tiles[y][x]
+                    })
+                    .collect()
+            })
+            .collect();
+        underground.write().map_err(|e| e.to_string())?.tiles = tiles.clone();
+        playback.checkpoint(PlaybackGranularity::Phase);
+    }
+
+    let entrances: Vec<(usize, usize)> = row_chunks(height)
+        .into_par_iter()
+        .enumerate()
+        .flat_map_iter(|(chunk, rows)| {
+            let mut rng = chunk_rng(seed, ENTRANCE_PHASE, chunk);
+            let mut chunk_entrances = Vec::new();
+            for y in rows {
+                for x in 0..width {
+                    let above = surface_tiles[y][x].environment;
+                    if tiles[y][x].environment != Environment::CAVERN
+                        || above == Environment::NONE
+                        || is_water[above.id() as usize]
+                    {
+                        continue;
+                    }
+                    let chance = if above == Environment::MOUNTAIN {
+                        config.entrance_chance_under_mountains
+                    } else {
+                        config.entrance_chance
+                    };
+                    if rng.gen_ratio(chance.min(1000), 1000) {
+                        chunk_entrances.push((x, y));
+                    }
+                }
+            }
+            chunk_entrances
+        })
+        .collect();
+
+    eprintln!("Generated underground with {} entrances", entrances.len());
+    underground.write().map_err(|e| e.to_string())?.entrances = entrances.clone();
+    surface.write().map_err(|e| e.to_string())?.entrances = entrances;
+    Ok(())
+}
+
+// Rock surrounded by mostly open hexes opens up (taking on the kind of cave most of them are),
+// caverns with hardly any open neighbours collapse
+fn smoothed_environment(
+    tiles: &[Vec<Hex>],
+    index: (usize, usize),
+    dimensions: (usize, usize),
+) -> Environment {
+    let (x, y) = index;
+    let current = tiles[y][x].environment;
+    let (mut caverns, mut flooded) = (0, 0);
+    for (n_x, n_y) in grid::neighbours(index, dimensions) {
+        match tiles[n_y][n_x].environment {
+            Environment::CAVERN => caverns += 1,
+            Environment::FLOODED_CAVE => flooded += 1,
+            _ => {}
+        }
+    }
+
+    if current == Environment::ROCK && caverns + flooded >= 4 {
+        if flooded > caverns {
+            Environment::FLOODED_CAVE
+        } else {
+            Environment::CAVERN
+        }
+    } else if current != Environment::ROCK && caverns + flooded < 2 {
+        Environment::ROCK
+    } else {
+        current
+    }
+}
@@ -0,0 +1,62 @@
+use crate::config::{band_rows, UnderseaConfig};
+use crate::map::chunks::{chunk_rng, row_chunks, snapshot, UNDERSEA_PHASE};
+use crate::map::environments::Environment;
+use crate::map::{areas, Hex, Map};
+use rand::Rng;
+use rayon::prelude::*;
+use std::sync::{Arc, RwLock};
+
+// Divides the sea into zones by the depth of each hex (which requires the elevation to be derived
+// already). Lakes remain aquatic. Shallows may grow reefs close to the equator or kelp forests
+// anywhere else
+pub fn classify(
+    map: &Arc<RwLock<Map>>,
+    dimensions: (u16, u16),
+    seed: u64,
+    config: &UnderseaConfig,
+) -> Result<(), String> {
+    let tiles = snapshot(map)?;
+    let dimensions = (dimensions.0 as usize, dimensions.1 as usize);
+    let height = dimensions.1;
+    let max_y = height - 1;
+    let lakes = areas::lakes(&tiles, config.max_lake_size);
+
+    let rows: Vec<Vec<Hex>> = row_chunks(height)
+        .into_par_iter()
+        .enumerate()
+        .flat_map_iter(|(chunk, rows)| {
+            let mut rng = chunk_rng(seed, UNDERSEA_PHASE, chunk);
+            let mut chunk_rows = Vec::with_capacity(rows.len());
+            for y in rows {
+                let tropical = (max_y / 2).abs_diff(y) < band_rows(config.reef_band, max_y);
+                let mut row = tiles[y].clone();
+                for (x, hex) in row.iter_mut().enumerate() {
+                    if hex.environment != Environment::AQUATIC || lakes[y][x] {
+                        continue;
+                    }
+                    let depth = -(hex.elevation as i32);
+                    hex.environment = if depth <= config.shallows_depth as i32 {
+                        if tropical && rng.gen_ratio(config.reef_chance.min(100), 100) {
+                            Environment::REEF
+                        } else if !tropical && rng.gen_ratio(config.kelp_chance.min(100), 100) {
+                            Environment::KELP_FOREST
+                        } else {
+                            Environment::SHALLOWS
+                        }
+                    } else if depth <= config.open_sea_depth as i32 {
+                        Environment::OPEN_SEA
+                    } else if depth <= config.deep_ocean_depth as i32 {
+                        Environment::DEEP_OCEAN
+                    } else {
+                        Environment::TRENCH
+                    };
+                }
+                chunk_rows.push(row);
+            }
+            chunk_rows
+        })
+        .collect();
+
+    map.write().map_err(|e| e.to_string())?.tiles = rows;
+    Ok(())
+}
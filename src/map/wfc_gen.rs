@@ -0,0 +1,460 @@
+use crate::config::GeneratorConfig;
+use crate::map::chunks::{chunk_rng, row_chunks, ChunkWriter};
+use crate::map::environments::Environment;
+use crate::map::grid;
+use crate::map::playback::Playback;
+use crate::map::{Hex, Map, MapGenerator};
+use rand::Rng;
+use rand_chacha::ChaCha8Rng;
+use rayon::prelude::*;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::ops::Range;
+use std::sync::{Arc, RwLock};
+
+// times a chunk is started over after running out of backtracks, the last attempt ignores the rows
+// of the chunks around it
+const ATTEMPTS: usize = 3;
+
+// Wave function collapse: every hex starts out with all weighted environments as possibilities.
+// The hex with the fewest possibilities left is collapsed to one of them, which rules out the
+// environments that may not be placed next to it in its neighbours (and so on). Choices that leave
+// a hex without any possibility are undone (see `WfcConfig`)
+pub struct WfcGenerator {
+    pub config: Arc<GeneratorConfig>,
+}
+
+impl MapGenerator for WfcGenerator {
+    fn populate(
+        &self,
+        map: Arc<RwLock<Map>>,
+        dimensions: (u16, u16),
+        seed: u64,
+        playback: &Playback,
+    ) {
+        let dimensions = (dimensions.0 as usize, dimensions.1 as usize);
+        let chunks = row_chunks(dimensions.1);
+        let writer = ChunkWriter::new(&map, playback);
+        let rules = Rules::new(&self.config);
+
+        // every second chunk is generated first and the remaining ones have to fit in between
+        let first_pass: Vec<Vec<Vec<Hex>>> = chunks
+            .par_iter()
+            .enumerate()
+            .filter(|(i, _)| i % 2 == 0)
+            .map(|(i, rows)| {
+                let rng = chunk_rng(seed, 0, i);
+                rules.generate_chunk(&writer, rng, rows.clone(), (None, None), dimensions)
+            })
+            .collect();
+        chunks
+            .par_iter()
+            .enumerate()
+            .filter(|(i, _)| i % 2 == 1)
+            .for_each(|(i, rows)| {
+                let above = first_pass[i / 2].last();
+                let below = first_pass.get(i / 2 + 1).and_then(|c| c.first());
+                let rng = chunk_rng(seed, 0, i);
+                rules.generate_chunk(&writer, rng, rows.clone(), (above, below), dimensions);
+            });
+    }
+
+    fn smooth(
+        &self,
+        _map: Arc<RwLock<Map>>,
+        _dimensions: (u16, u16),
+        _seed: u64,
+        _iteration: u16,
+        _playback: &Playback,
+    ) {
+        // smoothing would have to check the adjacency rules again, the clustering already takes
+        // care of larger areas of the same environment
+    }
+}
+
+// the configuration turned into bit masks over the weighted environments
+struct Rules {
+    environments: Vec<Environment>,
+    weights: Vec<u32>,
+    // the weighted environments allowed next to each environment (indexed by its id)
+    allowed: Vec<u64>,
+    clustering: u32,
+    max_backtracks: u32,
+}
+
+impl Rules {
+    fn new(config: &GeneratorConfig) -> Rules {
+        let wfc = &config.wfc;
+        let mut weighted: Vec<(Environment, u32)> = wfc
+            .weights
+            .iter()
+            .filter(|(_, &w)| w > 0)
+            .map(|(&e, &w)| (e, w))
+            .collect();
+        weighted.sort();
+        let (environments, weights): (Vec<_>, Vec<_>) = weighted.into_iter().unzip();
+
+        let lists = |a: Environment, b: Environment| {
+            a == b
+                || match wfc.neighbours.get(&a) {
+                    Some(listed) => listed.contains(&b),
+                    None => true,
+                }
+        };
+        let allowed = Environment::all()
+            .into_iter()
+            .map(|e| {
+                environments
+                    .iter()
+                    .enumerate()
+                    .filter(|&(_, &o)| lists(e, o) && lists(o, e))
+                    .fold(0u64, |mask, (i, _)| mask | 1 << i)
+            })
+            .collect();
+
+        Rules {
+            environments,
+            weights,
+            allowed,
+            clustering: wfc.clustering,
+            max_backtracks: wfc.max_backtracks,
+        }
+    }
+
+    fn all(&self) -> u64 {
+        match self.environments.len() {
+            64 => u64::MAX,
+            n => (1 << n) - 1,
+        }
+    }
+
+    // the weighted environments allowed next to any of the given ones
+    fn supported(&self, domain: u64) -> u64 {
+        bits(domain).fold(0, |mask, i| {
+            mask | self.allowed[self.environments[i].id() as usize]
+        })
+    }
+
+    fn generate_chunk(
+        &self,
+        writer: &ChunkWriter,
+        mut rng: ChaCha8Rng,
+        rows: Range<usize>,
+        // the rows of the chunks above/below that have already been generated
+        around: (Option<&Vec<Hex>>, Option<&Vec<Hex>>),
+        dimensions: (usize, usize),
+    ) -> Vec<Vec<Hex>> {
+        let width = dimensions.0;
+        let mut result = vec![vec![Hex::EMPTY; width]; rows.len()];
+        if self.environments.is_empty() {
+            for (y, row) in rows.clone().zip(&result) {
+                writer.row_done(y, row);
+            }
+            return result;
+        }
+
+        let mut chunk = Chunk::new(self, rows.clone(), around, dimensions);
+        let mut solved = false;
+        for attempt in 0..ATTEMPTS {
+            if attempt + 1 == ATTEMPTS {
+                chunk.fixed.iter_mut().for_each(|f| f.clear());
+            }
+            if chunk.solve(writer, &mut rng) {
+                solved = true;
+                break;
+            }
+        }
+        if !solved {
+            eprintln!(
+                "Failed to satisfy the adjacency rules in rows {}..{}",
+                rows.start, rows.end
+            );
+        }
+
+        for (cell, &domain) in chunk.domains.iter().enumerate() {
+            // whatever is left after a failure is filled with the most likely environment
+            let candidates = if domain == 0 { self.all() } else { domain };
+            let environment = bits(candidates)
+                .max_by_key(|&i| (self.weights[i], Reverse(i)))
+                .map_or(Environment::NONE, |i| self.environments[i]);
+            result[cell / width][cell % width].environment = environment;
+        }
+        for (y, row) in rows.zip(&result) {
+            writer.row_done(y, row);
+        }
+        result
+    }
+}
+
+// the state of the hexes of a chunk while it is being collapsed, cells are indexed row by row
+struct Chunk<'a> {
+    rules: &'a Rules,
+    width: usize,
+    first_row: usize,
+    // the neighbours of every cell within the chunk
+    adjacent: Vec<Vec<usize>>,
+    // the environments of the neighbours in the rows around the chunk
+    fixed: Vec<Vec<Environment>>,
+    // the environments every cell may still take on (bits of `Rules::environments`)
+    domains: Vec<u64>,
+    // previous domains of the cells that have been restricted, to be able to undo choices
+    trail: Vec<(usize, u64)>,
+    // cells by their amount of possibilities (and a random tie breaker), entries that are out of
+    // date are skipped
+    entropy: BinaryHeap<Reverse<(u32, u32, usize)>>,
+}
+
+impl<'a> Chunk<'a> {
+    fn new(
+        rules: &'a Rules,
+        rows: Range<usize>,
+        around: (Option<&Vec<Hex>>, Option<&Vec<Hex>>),
+        dimensions: (usize, usize),
+    ) -> Chunk<'a> {
+        let width = dimensions.0;
+        let cells = rows.len() * width;
+        let mut adjacent = vec![Vec::with_capacity(6); cells];
+        let mut fixed = vec![Vec::new(); cells];
+        for y in rows.clone() {
+            for x in 0..width {
+                let cell = (y - rows.start) * width + x;
+                for (n_x, n_y) in grid::neighbours((x, y), dimensions) {
+                    if rows.contains(&n_y) {
+                        adjacent[cell].push((n_y - rows.start) * width + n_x);
+                    } else if n_y < rows.start {
+                        fixed[cell].extend(around.0.map(|row| row[n_x].environment));
+                    } else {
+                        fixed[cell].extend(around.1.map(|row| row[n_x].environment));
+                    }
+                }
+            }
+        }
+        Chunk {
+            rules,
+            width,
+            first_row: rows.start,
+            adjacent,
+            fixed,
+            domains: Vec::new(),
+            trail: Vec::new(),
+            entropy: BinaryHeap::new(),
+        }
+    }
+
+    // collapses all cells, returns false if the adjacency rules could not be satisfied
+    fn solve(&mut self, writer: &ChunkWriter, rng: &mut ChaCha8Rng) -> bool {
+        let all = self.rules.all();
+        self.domains = self
+            .fixed
+            .iter()
+            .map(|fixed| {
+                fixed
+                    .iter()
+                    .fold(all, |d, e| d & self.rules.allowed[e.id() as usize])
+            })
+            .collect();
+        self.trail.clear();
+        self.entropy.clear();
+        for cell in 0..self.domains.len() {
+            self.push_entropy(cell, rng);
+        }
+        if self.domains.contains(&0) || !self.propagate((0..self.domains.len()).collect(), rng) {
+            return false;
+        }
+        // whether the environment of a cell is shown by the playback
+        let mut shown = vec![false; self.domains.len()];
+        for cell in 0..self.domains.len() {
+            self.show(cell, &mut shown, writer);
+        }
+
+        // the cell and environment of every choice and the length of the trail before it
+        let mut decisions: Vec<(usize, usize, usize)> = Vec::new();
+        let mut backtracks = 0;
+        while let Some(cell) = self.next_cell() {
+            let environment = self.choose(cell, rng);
+            let mut changed_from = self.trail.len();
+            decisions.push((cell, environment, changed_from));
+            let mut consistent = self.restrict(cell, 1 << environment, rng);
+            // cells whose possibilities have changed, including those restored by undoing choices
+            let mut changed = Vec::new();
+            while !consistent {
+                // undo the latest choice and rule it out instead
+                let (cell, environment, mark) = match decisions.pop() {
+                    Some(d) => d,
+                    None => return false,
+                };
+                backtracks += 1;
+                if backtracks > self.rules.max_backtracks {
+                    return false;
+                }
+                changed.extend(self.trail[mark..].iter().map(|&(c, _)| c));
+                self.undo(mark, rng);
+                changed_from = changed_from.min(mark);
+                consistent = self.restrict(cell, self.domains[cell] & !(1 << environment), rng);
+            }
+
+            changed.extend(self.trail[changed_from..].iter().map(|&(c, _)| c));
+            changed.sort_unstable();
+            changed.dedup();
+            for cell in changed {
+                self.show(cell, &mut shown, writer);
+            }
+        }
+        true
+    }
+
+    // shows the environment of a cell once it only has a single possibility left (and hides it
+    // again if a choice has been undone)
+    fn show(&self, cell: usize, shown: &mut [bool], writer: &ChunkWriter) {
+        let fixed = self.domains[cell].count_ones() == 1;
+        if fixed || shown[cell] {
+            let mut hex = Hex::EMPTY;
+            if fixed {
+                hex.environment =
+                    self.rules.environments[self.domains[cell].trailing_zeros() as usize];
+            }
+            writer.hex_done(cell % self.width, self.first_row + cell / self.width, hex);
+            shown[cell] = fixed;
+        }
+    }
+
+    fn push_entropy(&mut self, cell: usize, rng: &mut ChaCha8Rng) {
+        let count = self.domains[cell].count_ones();
+        if count > 1 {
+            self.entropy.push(Reverse((count, rng.gen(), cell)));
+        }
+    }
+
+    // the undecided cell with the fewest possibilities
+    fn next_cell(&mut self) -> Option<usize> {
+        while let Some(Reverse((count, _, cell))) = self.entropy.pop() {
+            if self.domains[cell].count_ones() == count {
+                return Some(cell);
+            }
+        }
+        None
+    }
+
+    // picks one of the possible environments by their weights, which increase for every neighbour
+    // that already has the same environment
+    fn choose(&self, cell: usize, rng: &mut ChaCha8Rng) -> usize {
+        let weight = |i: usize| {
+            let environment = self.rules.environments[i];
+            let same = self.adjacent[cell]
+                .iter()
+                .filter(|&&n| self.domains[n] == 1 << i)
+                .count()
+                + self.fixed[cell]
+                    .iter()
+                    .filter(|&&e| e == environment)
+                    .count();
+            self.rules.weights[i] as u64 * (100 + self.rules.clustering as u64 * same as u64)
+        };
+        let candidates: Vec<(usize, u64)> =
+            bits(self.domains[cell]).map(|i| (i, weight(i))).collect();
+        let mut n = rng.gen_range(0..candidates.iter().map(|&(_, w)| w).sum::<u64>());
+        for &(i, w) in &candidates {
+            if n < w {
+                return i;
+            }
+            n -= w;
+        }
+        candidates[candidates.len() - 1].0
+    }
+
+    // limits a cell to the given environments and propagates the consequences, returns false if
+    // any cell ends up without possibilities
+    fn restrict(&mut self, cell: usize, domain: u64, rng: &mut ChaCha8Rng) -> bool {
+        if domain == 0 {
+            return false;
+        }
+        self.set_domain(cell, domain, rng);
+        self.propagate(vec![cell], rng)
+    }
+
+    fn set_domain(&mut self, cell: usize, domain: u64, rng: &mut ChaCha8Rng) {
+        self.trail.push((cell, self.domains[cell]));
+        self.domains[cell] = domain;
+        self.push_entropy(cell, rng);
+    }
+
+    fn propagate(&mut self, mut changed: Vec<usize>, rng: &mut ChaCha8Rng) -> bool {
+        while let Some(cell) = changed.pop() {
+            let supported = self.rules.supported(self.domains[cell]);
+            for i in 0..self.adjacent[cell].len() {
+                let neighbour = self.adjacent[cell][i];
+                let domain = self.domains[neighbour] & supported;
+                if domain == self.domains[neighbour] {
+                    continue;
+                }
+                if domain == 0 {
+                    return false;
+                }
+                self.set_domain(neighbour, domain, rng);
+                changed.push(neighbour);
+            }
+        }
+        true
+    }
+
+    fn undo(&mut self, mark: usize, rng: &mut ChaCha8Rng) {
+        while self.trail.len() > mark {
+            if let Some((cell, domain)) = self.trail.pop() {
+                self.domains[cell] = domain;
+                self.push_entropy(cell, rng);
+            }
+        }
+    }
+}
+
+// indices of the set bits
+fn bits(mask: u64) -> impl Iterator<Item = usize> {
+    (0..64).filter(move |i| mask & 1 << i != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::map::{GeneratorKind, MapSettings, MapState};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn finished_maps_follow_the_adjacency_rules() {
+        let map_state = MapState::new(MapSettings {
+            dimensions: (40, 24),
+            iterations: 3,
+            erosion_iterations: 10,
+            smoothing_strength: 40,
+            seed: Some(7),
+            generator: GeneratorKind::WaveFunctionCollapse,
+        })
+        .unwrap();
+        while map_state.is_generating() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let neighbours = &config::current().wfc.neighbours;
+        let allows = |a: Environment, b: Environment| {
+            a == b
+                || match neighbours.get(&a) {
+                    Some(listed) => listed.contains(&b),
+                    None => true,
+                }
+        };
+        let map = map_state.map.read().unwrap();
+        let dimensions = (map.tiles[0].len(), map.tiles.len());
+        for (y, row) in map.tiles.iter().enumerate() {
+            for (x, hex) in row.iter().enumerate() {
+                for (n_x, n_y) in grid::neighbours((x, y), dimensions) {
+                    let (a, b) = (hex.environment, map.tiles[n_y][n_x].environment);
+                    assert!(
+                        allows(a, b) && allows(b, a),
+                        "{a:?} next to {b:?} at {:?}",
+                        (x, y)
+                    );
+                }
+            }
+        }
+    }
+}
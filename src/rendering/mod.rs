@@ -1,3 +1,8 @@
+pub mod text;
+
+use crate::map::grid;
+use crate::map::names::{Label, LabelKind};
+use crate::map::settlements::{Settlement, SettlementSize};
 use crate::map::MapState;
 use once_cell::sync::Lazy;
 use sdl2::gfx::primitives::DrawRenderer;
@@ -64,7 +69,7 @@ impl HexRenderer {
 
         for x in min_idx_x..=max_idx_x {
             for y in min_idx_y..=max_idx_y {
-                let map = map_state.map.read().map_err(|e| e.to_string())?;
+                let map = map_state.visible_map().read().map_err(|e| e.to_string())?;
                 let hex = map.tiles[y][x];
                 self.render_hex_indexed(
                     &canvas,
@@ -75,9 +80,202 @@ impl HexRenderer {
             }
         }
 
+        let map = map_state.visible_map().read().map_err(|e| e.to_string())?;
+        // roads are drawn on top of rivers, crossing them by bridges
+        let road_width = (self.y_radius / 5f32).round().max(1f32) as u8;
+        let river_width = (self.y_radius / 7f32).round().max(1f32) as u8;
+        for x in min_idx_x..=max_idx_x {
+            for y in min_idx_y..=max_idx_y {
+                let rivers = map.tiles[y][x].rivers;
+                if rivers != 0 {
+                    let color = Color::RGB(40, 110, 200);
+                    let index = (x as i16, y as i16);
+                    self.render_edges(canvas, index, viewport_offset, rivers, river_width, color)?;
+                }
+            }
+        }
+        for x in min_idx_x..=max_idx_x {
+            for y in min_idx_y..=max_idx_y {
+                let roads = map.tiles[y][x].roads;
+                if roads != 0 {
+                    let color = Color::RGB(190, 150, 90);
+                    let index = (x as i16, y as i16);
+                    self.render_edges(canvas, index, viewport_offset, roads, road_width, color)?;
+                }
+            }
+        }
+        for settlement in &map.settlements {
+            let (x, y) = settlement.position;
+            if (min_idx_x..=max_idx_x).contains(&x) && (min_idx_y..=max_idx_y).contains(&y) {
+                self.render_settlement(canvas, settlement, viewport_offset)?;
+            }
+        }
+        for &(x, y) in &map.entrances {
+            if (min_idx_x..=max_idx_x).contains(&x) && (min_idx_y..=max_idx_y).contains(&y) {
+                self.render_entrance(canvas, (x as i16, y as i16), viewport_offset)?;
+            }
+        }
+        for &(x, y) in &map_state.selection {
+            if (min_idx_x..=max_idx_x).contains(&x) && (min_idx_y..=max_idx_y).contains(&y) {
+                let (x_coordinates, y_coordinates) =
+                    self.hex_corners((x as i16, y as i16), viewport_offset);
+                let color = Color::RGB(255, 255, 255);
+                canvas.polygon(&x_coordinates, &y_coordinates, color)?;
+            }
+        }
+        if map_state.show_labels {
+            self.render_labels(canvas, &map.labels, viewport_offset)?;
+        }
+
         Ok(())
     }
 
+    // The names of the map in the order of their importance (see `names::name`), labels that would
+    // overlap a previously placed one are left out. The font of SDL2_gfx has 8x8 pixels per letter
+    fn render_labels<T: RenderTarget>(
+        &self,
+        canvas: &Canvas<T>,
+        labels: &[Label],
+        viewport_offset: (i16, i16),
+    ) -> Result<(), String> {
+        let (canvas_width, canvas_height) = canvas.output_size()?;
+        let mut placed: Vec<(i32, i32, i32, i32)> = Vec::new();
+        for label in labels {
+            let (x, y) = label.position;
+            let (center_x, center_y) = self.hex_center((x as i16, y as i16), viewport_offset);
+            let (text, color) = match label.kind {
+                LabelKind::Landmass => (label.text.to_uppercase(), Color::RGB(250, 240, 210)),
+                LabelKind::Water => (label.text.to_uppercase(), Color::RGB(170, 210, 255)),
+                LabelKind::Environment => (label.text.clone(), Color::RGB(235, 235, 190)),
+                LabelKind::River => (label.text.clone(), Color::RGB(140, 190, 255)),
+                LabelKind::Settlement(_) => (label.text.clone(), Color::RGB(255, 255, 255)),
+            };
+            let width = 8 * text.chars().count() as i32;
+            let left = match label.kind {
+                // next to the symbol of the settlement
+                LabelKind::Settlement(_) => center_x + self.y_radius * 0.5 + 2f32,
+                _ => center_x - width as f32 / 2f32,
+            };
+            let (left, top) = round_to_pixel_precision((left, center_y - 4f32));
+            let (left, top) = (left as i32, top as i32);
+            let (right, bottom) = (left + width, top + 8);
+            if right < 0 || bottom < 0 || left >= canvas_width as i32 || top >= canvas_height as i32
+            {
+                continue;
+            }
+            // keeping a little space between labels
+            let overlaps = placed.iter().any(|&(l, t, r, b)| {
+                left < r + 4 && l < right + 4 && top < b + 2 && t < bottom + 2
+            });
+            if overlaps {
+                continue;
+            }
+            placed.push((left, top, right, bottom));
+            // the shadow keeps the labels readable on light environments
+            let shadow = Color::RGB(20, 20, 20);
+            canvas.string(left as i16 + 1, top as i16 + 1, &text, shadow)?;
+            canvas.string(left as i16, top as i16, &text, color)?;
+        }
+        Ok(())
+    }
+
+    // draws a line from the middle of the hex to the middle of every edge in the bit mask (roads or
+    // rivers), so that the lines of neighbouring hexes connect
+    fn render_edges<T: RenderTarget>(
+        &self,
+        canvas: &Canvas<T>,
+        index: (i16, i16),
+        viewport_offset: (i16, i16),
+        edges: u8,
+        width: u8,
+        color: Color,
+    ) -> Result<(), String> {
+        let (center_x, center_y) = self.hex_center(index, viewport_offset);
+
+        for direction in grid::DIRECTIONS {
+            if edges & (1 << direction) == 0 {
+                continue;
+            }
+            // half the distance to the middle point of the neighbour in that direction
+            let (dx, dy) = match direction {
+                grid::TOP_LEFT => (-self.x_radius / 2f32, -self.tiling_height / 2f32),
+                grid::TOP_RIGHT => (self.x_radius / 2f32, -self.tiling_height / 2f32),
+                grid::RIGHT => (self.x_radius, 0f32),
+                grid::BOTTOM_RIGHT => (self.x_radius / 2f32, self.tiling_height / 2f32),
+                grid::BOTTOM_LEFT => (-self.x_radius / 2f32, self.tiling_height / 2f32),
+                _ => (-self.x_radius, 0f32),
+            };
+            let start = round_to_pixel_precision((center_x, center_y));
+            let end = round_to_pixel_precision((center_x + dx, center_y + dy));
+            canvas.thick_line(start.0, start.1, end.0, end.1, width, color)?;
+        }
+        Ok(())
+    }
+
+    // a downwards pointing triangle, shown on both layers
+    fn render_entrance<T: RenderTarget>(
+        &self,
+        canvas: &Canvas<T>,
+        index: (i16, i16),
+        viewport_offset: (i16, i16),
+    ) -> Result<(), String> {
+        let (center_x, center_y) = self.hex_center(index, viewport_offset);
+        let size = (self.y_radius * 0.4).max(1f32);
+        let left = round_to_pixel_precision((center_x - size, center_y - size / 2f32));
+        let right = round_to_pixel_precision((center_x + size, center_y - size / 2f32));
+        let bottom = round_to_pixel_precision((center_x, center_y + size));
+        canvas.filled_trigon(
+            left.0,
+            left.1,
+            right.0,
+            right.1,
+            bottom.0,
+            bottom.1,
+            Color::RGB(15, 10, 10),
+        )?;
+        canvas.trigon(
+            left.0,
+            left.1,
+            right.0,
+            right.1,
+            bottom.0,
+            bottom.1,
+            Color::RGB(240, 200, 60),
+        )
+    }
+
+    fn render_settlement<T: RenderTarget>(
+        &self,
+        canvas: &Canvas<T>,
+        settlement: &Settlement,
+        viewport_offset: (i16, i16),
+    ) -> Result<(), String> {
+        let (x, y) = settlement.position;
+        let (center_x, center_y) =
+            round_to_pixel_precision(self.hex_center((x as i16, y as i16), viewport_offset));
+        let fill = Color::RGB(30, 30, 30);
+        let outline = Color::RGB(240, 240, 240);
+
+        match settlement.size {
+            SettlementSize::Village => {
+                let radius = (self.y_radius * 0.25).round().max(1f32) as i16;
+                canvas.filled_circle(center_x, center_y, radius, fill)
+            }
+            SettlementSize::Town => {
+                let radius = (self.y_radius * 0.35).round().max(1f32) as i16;
+                canvas.filled_circle(center_x, center_y, radius, fill)?;
+                canvas.circle(center_x, center_y, radius, outline)
+            }
+            SettlementSize::City => {
+                let half_size = (self.y_radius * 0.45).round().max(1f32) as i16;
+                let (x1, y1) = (center_x - half_size, center_y - half_size);
+                let (x2, y2) = (center_x + half_size, center_y + half_size);
+                canvas.box_(x1, y1, x2, y2, fill)?;
+                canvas.rectangle(x1, y1, x2, y2, outline)
+            }
+        }
+    }
+
     fn render_hex_indexed<T: RenderTarget>(
         &self,
         canvas: &Canvas<T>,
@@ -86,15 +284,12 @@ impl HexRenderer {
         // the distance from the middle point to a corner of the hex
         color: Color,
     ) -> Result<(), String> {
-        let (x_i, y_i) = index;
-        let (x, y) = (x_i as f32, y_i as f32);
-        let (offset_x, offset_y) = (viewport_offset.0 as f32, viewport_offset.1 as f32);
-
-        // every 2nd row needs to be shifted by half a hex for a continuous pattern
-        let row_offset = ((y_i % 2) as f32) * self.x_radius;
+        let (x_coordinates, y_coordinates) = self.hex_corners(index, viewport_offset);
+        canvas.filled_polygon(&x_coordinates, &y_coordinates, color)
+    }
 
-        let center_x = x * self.hex_width + self.x_radius - offset_x + row_offset;
-        let center_y = y * self.tiling_height + self.y_radius - offset_y;
+    fn hex_corners(&self, index: (i16, i16), viewport_offset: (i16, i16)) -> ([i16; 6], [i16; 6]) {
+        let (center_x, center_y) = self.hex_center(index, viewport_offset);
 
         let p1 = round_to_pixel_precision((center_x, center_y - self.y_radius)); // top
         let p2 = round_to_pixel_precision((center_x + self.x_radius, center_y - self.half_radius)); // top-right
@@ -103,10 +298,39 @@ impl HexRenderer {
         let p5 = round_to_pixel_precision((center_x - self.x_radius, center_y + self.half_radius)); // bottom-left
         let p6 = round_to_pixel_precision((center_x - self.x_radius, center_y - self.half_radius)); // top-left
 
-        let x_coordinates = &[p1.0, p2.0, p3.0, p4.0, p5.0, p6.0];
-        let y_coordinates = &[p1.1, p2.1, p3.1, p4.1, p5.1, p6.1];
+        (
+            [p1.0, p2.0, p3.0, p4.0, p5.0, p6.0],
+            [p1.1, p2.1, p3.1, p4.1, p5.1, p6.1],
+        )
+    }
+
+    // position of the hex's middle point on the canvas
+    pub fn hex_center(&self, index: (i16, i16), viewport_offset: (i16, i16)) -> (f32, f32) {
+        let (x_i, y_i) = index;
+        let (x, y) = (x_i as f32, y_i as f32);
+        let (offset_x, offset_y) = (viewport_offset.0 as f32, viewport_offset.1 as f32);
+
+        // every 2nd row needs to be shifted by half a hex for a continuous pattern
+        let row_offset = ((y_i % 2) as f32) * self.x_radius;
+
+        let center_x = x * self.hex_width + self.x_radius - offset_x + row_offset;
+        let center_y = y * self.tiling_height + self.y_radius - offset_y;
+        (center_x, center_y)
+    }
 
-        canvas.filled_polygon(x_coordinates, y_coordinates, color)
+    // the hex at the pixel of the canvas, `None` outside of the map
+    pub fn hex_at(
+        &self,
+        pixel: (i32, i32),
+        viewport_offset: (i16, i16),
+        map_size: (u16, u16),
+    ) -> Option<(usize, usize)> {
+        // relative to the middle point of the hex (0, 0), in hex widths (see `grid::center`)
+        let x = (pixel.0 as f32 + viewport_offset.0 as f32 - self.x_radius) / self.hex_width;
+        let y = (pixel.1 as f32 + viewport_offset.1 as f32 - self.y_radius) / self.hex_width;
+        let (x, y) = grid::hex_at((x, y));
+        let (width, height) = (map_size.0 as i64, map_size.1 as i64);
+        ((0..width).contains(&x) && (0..height).contains(&y)).then_some((x as usize, y as usize))
     }
 
     // returns minimum and maximum index of tiles to be rendered
@@ -0,0 +1,153 @@
+use crate::map::environments::Environment;
+use crate::map::settlements::SettlementSize;
+use crate::map::Map;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write as _;
+
+const RESET: &str = "\x1b[0m";
+// bold white
+const SETTLEMENT_CODE: &str = "\x1b[1;97m";
+// settlements are drawn on top of the environment of their hex
+const VILLAGE_GLYPH: char = 'v';
+const TOWN_GLYPH: char = 't';
+const CITY_GLYPH: char = 'C';
+
+// Renders the hexes between the first and last (inclusive) as text, every hex as the glyph of its
+// environment followed by a space and every second row shifted by a character like on screen. With
+// `colour`, the glyphs are coloured like the environments using ANSI escape codes (24 bit colours),
+// without the output is plain text that only changes when the map does. A legend of the
+// environments and settlements shown follows the map
+pub fn render_text(map: &Map, first: (usize, usize), last: (usize, usize), colour: bool) -> String {
+    let height = map.tiles.len();
+    let width = map.tiles.first().map_or(0, |row| row.len());
+    if width == 0 || height == 0 {
+        return String::new();
+    }
+    let (last_x, last_y) = (last.0.min(width - 1), last.1.min(height - 1));
+    let settlements: HashMap<(usize, usize), SettlementSize> = map
+        .settlements
+        .iter()
+        .map(|s| (s.position, s.size))
+        .collect();
+
+    // writing to a `String` cannot fail
+    let mut text = String::new();
+    let mut environments = BTreeSet::new();
+    let mut sizes = BTreeSet::new();
+    for y in first.1..=last_y {
+        let mut current = None;
+        if y % 2 == 1 {
+            text.push(' ');
+        }
+        for x in first.0..=last_x {
+            let environment = map.tiles[y][x].environment;
+            environments.insert(environment);
+            let (glyph, code) = match settlements.get(&(x, y)) {
+                Some(&size) => {
+                    sizes.insert(size);
+                    (settlement_glyph(size), String::from(SETTLEMENT_CODE))
+                }
+                None => (environment.glyph(), escape_code(environment)),
+            };
+            // the escape code is only repeated when the colour changes
+            if colour && current.as_ref() != Some(&code) {
+                if current.is_some() {
+                    text.push_str(RESET);
+                }
+                text.push_str(&code);
+                current = Some(code);
+            }
+            text.push(glyph);
+            text.push(' ');
+        }
+        if colour {
+            text.push_str(RESET);
+        }
+        text.push('\n');
+    }
+
+    text.push('\n');
+    for environment in environments {
+        let glyph = if colour {
+            format!("{}{}{RESET}", escape_code(environment), environment.glyph())
+        } else {
+            environment.glyph().to_string()
+        };
+        let _ = writeln!(text, "{glyph} {}", environment.name());
+    }
+    for size in sizes {
+        let glyph = if colour {
+            format!("{SETTLEMENT_CODE}{}{RESET}", settlement_glyph(size))
+        } else {
+            settlement_glyph(size).to_string()
+        };
+        let _ = writeln!(text, "{glyph} {size:?}");
+    }
+    text
+}
+
+fn settlement_glyph(size: SettlementSize) -> char {
+    match size {
+        SettlementSize::Village => VILLAGE_GLYPH,
+        SettlementSize::Town => TOWN_GLYPH,
+        SettlementSize::City => CITY_GLYPH,
+    }
+}
+
+fn escape_code(environment: Environment) -> String {
+    let color = environment.color();
+    format!("\x1b[38;2;{};{};{}m", color.r, color.g, color.b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::{GeneratorKind, MapSettings, MapState};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn renders_a_small_map() {
+        let map_state = MapState::new(MapSettings {
+            dimensions: (24, 12),
+            iterations: 3,
+            erosion_iterations: 10,
+            smoothing_strength: 40,
+            seed: Some(7),
+            generator: GeneratorKind::Procedural,
+        })
+        .unwrap();
+        while map_state.is_generating() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let map = map_state.map.read().unwrap();
+        let text = render_text(&map, (0, 0), (usize::MAX, usize::MAX), false);
+        let expected = concat!(
+            "= * * # # # # # # ^ = = = - $ # # = = = * # * # \n",
+            " - ^ ^ % % * = ^ ^ = * = = - - - - = = = = = = - \n",
+            "- ^ ^ % ^ ^ = - ^ ^ = = \" \" - - - ^ = = = = = = \n",
+            " = ^ T ^ ^ ^ $ - - = = = - - - - $ - - - - = = = \n",
+            "= ^ ^ T - = = = - ^ = = - - - = = - - - - \" \" = \n",
+            " - ^ - + + = = = - = = - T T - = = - = - - T T = \n",
+            "= - - - T - $ T = = = = - - = - ^ = - = - - = = \n",
+            " = - - = = - - - = \" = = = = = - - - - = $ = = = \n",
+            "= = = - = = = = = \" = = = = = ^ = \" - - = = = = \n",
+            " = = = - = = = = = = - - = = = = = = = = = - - - \n",
+            "- - = - ^ = = = = = = * - $ = = = = = = = = = * \n",
+            " - = = - # - = = # # # - - * * - = = = W = = - * \n",
+            "\n",
+            "* Arctic\n",
+            "T Forest\n",
+            "^ Mountain\n",
+            "\" Plains\n",
+            "% Swamp\n",
+            "# Glacier\n",
+            "- Shallows\n",
+            "= Open sea\n",
+            "W Deep ocean\n",
+            "+ Reef\n",
+            "$ Kelp forest\n",
+        );
+        assert_eq!(text, expected);
+    }
+}